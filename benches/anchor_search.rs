@@ -0,0 +1,82 @@
+//! Compares anchor search throughput of `regex` against `memchr::memmem`
+//! on the reads of `data/test/test.fastq.gz`.
+//!
+//! Run with `cargo bench --bench anchor_search`
+
+use std::time::{Duration, Instant};
+use memchr::memmem::Finder;
+use regex::bytes::Regex;
+use screenr::crispr::{Library, reverse_complement};
+use screenr::reader::{FastqGz, FastqRead};
+
+const GUIDE: &str = "GTTTAAGAG";
+const ROUNDS: usize = 200;
+
+/// Loads all sequences of the test fastq into memory
+fn load_reads() -> Vec<Vec<u8>> {
+    let mut reader = FastqGz::new("data/test/test.fastq.gz")
+        .expect("Unable to open reader");
+    let mut reads = Vec::new();
+    while let Some(rec) = reader.next_record_ref() {
+        reads.push(rec.get_seq().to_vec());
+    }
+    reads
+}
+
+/// Times a search over every read for a number of rounds
+fn time_search<F: FnMut(&[u8]) -> bool>(reads: &[Vec<u8>], mut search: F) -> (Duration, usize) {
+    let start = Instant::now();
+    let mut n_found = 0;
+    for _ in 0..ROUNDS {
+        for read in reads {
+            if search(std::hint::black_box(read)) {
+                n_found += 1;
+            }
+        }
+    }
+    (start.elapsed(), n_found)
+}
+
+fn report(label: &str, n_reads: usize, elapsed: Duration) {
+    let total = (n_reads * ROUNDS) as f64;
+    println!(
+        "{:<24}{:>10.2} ms{:>14.2} M reads/sec",
+        label,
+        elapsed.as_secs_f64() * 1e3,
+        total / elapsed.as_secs_f64() / 1e6
+    );
+}
+
+fn main() {
+    let reads = load_reads();
+    let rc_guide = reverse_complement(GUIDE);
+
+    // regex anchor search (previous implementation)
+    let fwd_regex = Regex::new(GUIDE).expect("Unable to build regex");
+    let rev_regex = Regex::new(&rc_guide).expect("Unable to build regex");
+    let (regex_time, regex_found) = time_search(&reads, |read| {
+        fwd_regex.is_match(read) || rev_regex.is_match(read)
+    });
+
+    // memmem anchor search (current implementation)
+    let fwd_finder = Finder::new(GUIDE);
+    let rev_finder = Finder::new(&rc_guide);
+    let (memmem_time, memmem_found) = time_search(&reads, |read| {
+        fwd_finder.find(read).is_some() || rev_finder.find(read).is_some()
+    });
+    assert_eq!(regex_found, memmem_found, "searchers disagree on anchor matches");
+
+    // full matching path through the library
+    let mut library = Library::new(GUIDE, 1);
+    library.load_library("data/test/library.fasta")
+        .expect("Unable to load library");
+    let (library_time, _) = time_search(&reads, |read| {
+        library.match_seq_bytes(read, 0);
+        true
+    });
+
+    println!("{} reads x {} rounds", reads.len(), ROUNDS);
+    report("regex anchor", reads.len(), regex_time);
+    report("memmem anchor", reads.len(), memmem_time);
+    report("library matching", reads.len(), library_time);
+}
@@ -0,0 +1,99 @@
+//! Times counting a realistically sized sample end to end: decompressing and
+//! parsing the fastq, searching the guide sequence and assigning reads to the
+//! full CRISPRi v2 library.
+//!
+//! Reads are simulated from the library guides (half of them reverse
+//! complemented, a fifth without any guide) and written to a temporary
+//! gzipped fastq before timing.
+//!
+//! Run with `cargo bench --bench count`
+
+use std::{fs::File, io::{BufWriter, Write}, time::Instant};
+use flate2::{Compression, write::GzEncoder};
+use screenr::analysis::Rng;
+use screenr::crispr::{Library, reverse_complement};
+use screenr::reader::{FastaGz, FastaRead, FastqGz};
+
+const GUIDE: &str = "GTTTAAGAG";
+const LIBRARY: &str = "data/libraries/CRISPRi_v2_crop28.all.fasta.gz";
+const N_READS: usize = 2_000_000;
+const PREFIX_LEN: usize = 8;
+const READ_LEN: usize = 66;
+
+/// Loads the guide sequences of the library
+fn load_guides() -> Vec<String> {
+    let mut reader = FastaGz::new(LIBRARY)
+        .expect("Unable to open library");
+    let mut guides = Vec::new();
+    while let Some(rec) = reader.next_record() {
+        guides.push(rec.get_seq().to_string());
+    }
+    guides
+}
+
+/// Random bases of the given length
+fn random_bases(rng: &mut Rng, len: usize) -> String {
+    (0..len).map(|_| ['A', 'C', 'G', 'T'][rng.below(4)]).collect()
+}
+
+/// Writes simulated reads to a gzipped fastq and returns the number carrying a guide
+fn simulate_reads(filename: &str, guides: &[String]) -> usize {
+    let mut rng = Rng::new(42);
+    let mut writer = GzEncoder::new(
+        BufWriter::new(File::create(filename).expect("Unable to create fastq")),
+        Compression::default()
+    );
+    let qual = "F".repeat(READ_LEN);
+    let mut n_guides = 0;
+    for i in 0..N_READS {
+        let read = if rng.below(5) == 0 {
+            random_bases(&mut rng, READ_LEN)
+        } else {
+            n_guides += 1;
+            let guide = &guides[rng.below(guides.len())];
+            let read = format!(
+                "{}{}{}",
+                random_bases(&mut rng, PREFIX_LEN),
+                guide,
+                random_bases(&mut rng, READ_LEN - PREFIX_LEN - guide.len())
+            );
+            if rng.below(2) == 0 { read } else { reverse_complement(&read) }
+        };
+        writeln!(writer, "@read{}\n{}\n+\n{}", i, read, qual).expect("Unable to write fastq");
+    }
+    writer.finish()
+        .and_then(|mut w| w.flush())
+        .expect("Unable to write fastq");
+    n_guides
+}
+
+fn main() {
+    let filename = std::env::temp_dir().join(format!("screenr_bench_{}.fastq.gz", std::process::id()));
+    let filename = filename.to_str().unwrap();
+    let guides = load_guides();
+    let n_guides = simulate_reads(filename, &guides);
+
+    let start = Instant::now();
+    let mut library = Library::new(GUIDE, 1);
+    library.load_library(LIBRARY)
+        .expect("Unable to load library");
+    let load_time = start.elapsed();
+
+    let start = Instant::now();
+    let mut reader = FastqGz::new(filename)
+        .expect("Unable to open reader");
+    library.match_reader(&mut reader, 0);
+    let count_time = start.elapsed();
+    std::fs::remove_file(filename).expect("Unable to remove fastq");
+
+    let stats = library.sample_stats(0);
+    println!("{} guides, {} reads ({} with a guide)", library.num_guides(), N_READS, n_guides);
+    println!("{:<24}{:>10.2} ms", "library loading", load_time.as_secs_f64() * 1e3);
+    println!(
+        "{:<24}{:>10.2} ms{:>14.2} M reads/sec",
+        "counting",
+        count_time.as_secs_f64() * 1e3,
+        N_READS as f64 / count_time.as_secs_f64() / 1e6
+    );
+    println!("{:<24}{:>10}", "assigned reads", stats.num_assigned);
+}
@@ -0,0 +1,69 @@
+use std::io::{Error, Write};
+use super::CountTable;
+
+/// Replicate-averaged comparison of a single guide between two sample groups
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GuideComparison {
+    /// Mean normalized count of the reference samples
+    pub reference: f64,
+    /// Mean normalized count of the treatment samples
+    pub treatment: f64,
+    /// Difference of the mean log2 normalized counts (treatment - reference)
+    pub log2fc: f64
+}
+
+fn mean<I: Iterator<Item = f64>>(values: I) -> f64 {
+    let (sum, n) = values.fold((0.0, 0), |(sum, n), x| (sum + x, n + 1));
+    sum / n as f64
+}
+
+/// Computes per-guide log2 fold changes between groups of samples
+///
+/// `normalized` is the guide x sample matrix of normalized (pseudo)counts
+/// and `reference` / `treatment` are sample indices of the table.
+pub fn compare_groups(table: &CountTable, normalized: &[f64], reference: &[usize], treatment: &[usize]) -> Vec<GuideComparison> {
+    let n = table.num_samples();
+    normalized
+        .chunks(n)
+        .map(|row| {
+            let log_ref = mean(reference.iter().map(|s| row[*s].log2()));
+            let log_trt = mean(treatment.iter().map(|s| row[*s].log2()));
+            GuideComparison {
+                reference: mean(reference.iter().map(|s| row[*s])),
+                treatment: mean(treatment.iter().map(|s| row[*s])),
+                log2fc: log_trt - log_ref
+            }
+        })
+        .collect()
+}
+
+/// Writes guide comparisons as a TSV
+pub fn write_comparisons<W: Write>(writer: &mut W, table: &CountTable, comparisons: &[GuideComparison]) -> Result<(), Error> {
+    writeln!(writer, "sgRNA\tGene\tReference\tTreatment\tLog2FC")?;
+    for (guide, c) in comparisons.iter().enumerate() {
+        writeln!(
+            writer,
+            "{}\t{}\t{:.4}\t{:.4}\t{:.6}",
+            table.guides[guide], table.genes[guide], c.reference, c.treatment, c.log2fc
+        )?;
+    }
+    writer.flush()
+}
+
+#[test]
+fn test_compare_groups() {
+    let table = CountTable {
+        guides: vec!["a".into(), "b".into()],
+        genes: vec!["A".into(), "B".into()],
+        controls: vec![false, false],
+        samples: vec!["r1".into(), "r2".into(), "t1".into(), "t2".into()],
+        counts: vec![
+            1.0, 4.0, 4.0, 16.0,
+            8.0, 8.0, 2.0, 2.0
+        ]
+    };
+    let cmp = compare_groups(&table, &table.counts, &[0, 1], &[2, 3]);
+    assert_eq!(cmp[0].reference, 2.5);
+    assert_eq!(cmp[0].log2fc, 2.0);
+    assert_eq!(cmp[1].log2fc, -2.0);
+}
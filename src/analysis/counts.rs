@@ -0,0 +1,136 @@
+use std::{fs::File, io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write}};
+use crate::crispr::{ControlSpec, Library};
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Guide x sample count matrix used by the downstream analyses
+#[derive(Debug, Clone, Default)]
+pub struct CountTable {
+    pub guides: Vec<String>,
+    pub genes: Vec<String>,
+    pub controls: Vec<bool>,
+    pub samples: Vec<String>,
+    /// Row-major counts (guides x samples)
+    pub counts: Vec<f64>
+}
+impl CountTable {
+
+    /// Collects the counts of a matched library
+    pub fn from_library(library: &Library, samples: &[&str]) -> Self {
+        let mut table = Self {
+            samples: samples.iter().map(|x| x.to_string()).collect(),
+            ..Self::default()
+        };
+        for guide in 0..library.num_guides() {
+            table.guides.push(library.get_name(guide).to_string());
+            table.genes.push(library.get_gene(guide).to_string());
+            table.controls.push(library.is_control(guide));
+            table.counts.extend(library.get_counts(guide).iter().map(|c| *c as f64));
+        }
+        table
+    }
+
+    /// Reads the named sample columns of a count table written by screenr
+    ///
+    /// Guides are labelled as controls using the provided designation.
+    pub fn read(filename: &str, samples: &[&str], control_spec: &ControlSpec) -> Result<Self, Error> {
+        let mut lines = BufReader::new(File::open(filename)?).lines();
+        let header: Vec<String> = match lines.next() {
+            Some(line) => line?.split('\t').map(|x| x.to_string()).collect(),
+            None => return Err(invalid(format!("count table '{}' is empty", filename)))
+        };
+        if header.len() < 2 || header[0] != "sgRNA" || header[1] != "Gene" {
+            return Err(invalid(format!("'{}' is not a screenr count table (expected sgRNA and Gene columns)", filename)));
+        }
+        let columns = samples
+            .iter()
+            .map(|name| {
+                header.iter()
+                    .position(|x| x == name)
+                    .ok_or_else(|| invalid(format!("sample '{}' not found in count table '{}'", name, filename)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut table = Self {
+            samples: samples.iter().map(|x| x.to_string()).collect(),
+            ..Self::default()
+        };
+        for (line_idx, line) in lines.enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != header.len() {
+                return Err(invalid(format!(
+                    "line {} of '{}' has {} fields but the header has {}",
+                    line_idx + 2, filename, fields.len(), header.len()
+                )));
+            }
+            for col in columns.iter() {
+                let count = fields[*col].parse::<f64>()
+                    .map_err(|_| invalid(format!(
+                        "line {} of '{}' has a non-numeric count '{}'",
+                        line_idx + 2, filename, fields[*col]
+                    )))?;
+                table.counts.push(count);
+            }
+            table.controls.push(control_spec.is_control(fields[0], fields[1]));
+            table.guides.push(fields[0].to_string());
+            table.genes.push(fields[1].to_string());
+        }
+        Ok(table)
+    }
+
+    /// Index of a sample in the table
+    pub fn sample_index(&self, name: &str) -> Option<usize> {
+        self.samples.iter().position(|x| x == name)
+    }
+
+    /// Number of guides in the table
+    pub fn num_guides(&self) -> usize {
+        self.guides.len()
+    }
+
+    /// Number of samples in the table
+    pub fn num_samples(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Counts of a guide across all samples
+    pub fn row(&self, guide: usize) -> &[f64] {
+        let n = self.num_samples();
+        &self.counts[guide * n .. (guide + 1) * n]
+    }
+
+    /// Counts of a single sample across all guides
+    pub fn column(&self, sample: usize) -> impl Iterator<Item = f64> + '_ {
+        self.counts
+            .iter()
+            .skip(sample)
+            .step_by(self.num_samples().max(1))
+            .copied()
+    }
+
+    /// Writes a guide x sample matrix of values using the table's row and column labels
+    pub fn write_values(&self, filename: &str, values: &[f64]) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        write!(writer, "sgRNA\tGene")?;
+        for s in self.samples.iter() {
+            write!(writer, "\t{}", s)?;
+        }
+        writeln!(writer)?;
+
+        let n = self.num_samples();
+        for (guide, row) in values.chunks(n.max(1)).enumerate().take(self.num_guides()) {
+            write!(writer, "{}\t{}", self.guides[guide], self.genes[guide])?;
+            for v in row {
+                write!(writer, "\t{:.4}", v)?;
+            }
+            writeln!(writer)?;
+        }
+        writer.flush()
+    }
+}
@@ -0,0 +1,210 @@
+use std::{collections::HashMap, io::{Error, ErrorKind, Write}};
+use super::{CountTable, Phenotype};
+use super::phenotype::{mean_top, top_guides};
+use super::stats::{Rng, mann_whitney_u, std_dev};
+
+/// Gene-level statistics of a single phenotype
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneStats {
+    pub gene: String,
+    pub n_guides: usize,
+    /// Mean phenotype of the strongest guides
+    pub score: f64,
+    /// Mann-Whitney p-value of the strongest guides against the control guides
+    pub pvalue: f64,
+    /// Score in units of the control standard deviation times -log10(p-value)
+    pub product: f64,
+    /// False discovery rate estimated from control pseudogenes
+    pub fdr: f64
+}
+
+/// Scores the strongest guides of a group against the control distribution
+fn score_group(gene: String, scores: Vec<f64>, controls: &[f64], control_sd: f64, top_n: usize) -> GeneStats {
+    let n_guides = scores.len();
+    let top = top_guides(scores, top_n);
+    let score = mean_top(&top);
+    let (_, pvalue) = mann_whitney_u(&top, controls);
+    GeneStats {
+        gene,
+        n_guides,
+        score,
+        pvalue,
+        product: score / control_sd * -pvalue.max(f64::MIN_POSITIVE).log10(),
+        fdr: 1.0
+    }
+}
+
+/// Calls gene-level hits of a phenotype
+///
+/// Each gene's `top_n` strongest guides are compared against the control
+/// guides with a Mann-Whitney U test. Pseudogenes, built by sampling control
+/// guides into groups sized like randomly drawn genes, are scored the same way
+/// and the FDR of a product score is the fraction of pseudogenes reaching it
+/// relative to the fraction of genes reaching it.
+pub fn gene_statistics(table: &CountTable, phenotype: &Phenotype, top_n: usize, num_pseudogenes: Option<usize>, rng: &mut Rng) -> Result<Vec<GeneStats>, Error> {
+    if top_n == 0 {
+        return Err(Error::new(ErrorKind::InvalidInput, "at least one guide per gene is needed for gene-level statistics"));
+    }
+    let controls = phenotype.control_scores(table);
+    if controls.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, "no control guides found to compare genes against"));
+    }
+    let control_sd = match std_dev(&controls) {
+        sd if sd > 0.0 => sd,
+        _ => 1.0
+    };
+
+    let groups = phenotype.gene_groups(table);
+    let sizes: Vec<usize> = groups.iter().map(|(_, s)| s.len()).collect();
+    let num_pseudogenes = num_pseudogenes.unwrap_or(groups.len()).max(1);
+
+    let mut genes: Vec<GeneStats> = groups
+        .into_iter()
+        .map(|(gene, scores)| score_group(gene, scores, &controls, control_sd, top_n))
+        .collect();
+    let pseudo_products: Vec<f64> = (0..num_pseudogenes)
+        .map(|idx| {
+            let size = match sizes.len() {
+                0 => top_n,
+                n => sizes[rng.below(n)]
+            };
+            let scores = rng.sample(&controls, size);
+            score_group(format!("pseudogene_{}", idx), scores, &controls, control_sd, top_n)
+                .product
+                .abs()
+        })
+        .collect();
+
+    // empirical FDR, made monotone in the product score
+    let n_genes = genes.len() as f64;
+    let mut order: Vec<usize> = (0..genes.len()).collect();
+    order.sort_by(|a, b| genes[*a].product.abs().total_cmp(&genes[*b].product.abs()));
+    let mut running = 1.0_f64;
+    for (pos, idx) in order.iter().enumerate() {
+        let threshold = genes[*idx].product.abs();
+        let pseudo_rate = pseudo_products.iter().filter(|p| **p >= threshold).count() as f64 / num_pseudogenes as f64;
+        let gene_rate = (genes.len() - pos) as f64 / n_genes;
+        running = running.min((pseudo_rate / gene_rate).min(1.0));
+        genes[*idx].fdr = running;
+    }
+    Ok(genes)
+}
+
+/// Writes gene-level statistics of one or more phenotypes as a TSV
+///
+/// Phenotypes are joined on gene name since each drops the genes without
+/// finite scores separately. Genes are listed in order of first appearance,
+/// with `NA` cells for phenotypes lacking them, and the guide count is the
+/// largest number of scored guides across phenotypes.
+pub fn write_gene_stats<W: Write>(writer: &mut W, phenotypes: &[Phenotype], stats: &[Vec<GeneStats>]) -> Result<(), Error> {
+    write!(writer, "Gene\tGuides")?;
+    for p in phenotypes {
+        write!(writer, "\t{0}\t{0}_pvalue\t{0}_product\t{0}_fdr", p.name)?;
+    }
+    writeln!(writer)?;
+
+    let mut genes = Vec::new();
+    let by_gene: Vec<HashMap<&str, &GeneStats>> = stats
+        .iter()
+        .map(|s| {
+            s.iter()
+                .map(|g| {
+                    if !genes.contains(&g.gene.as_str()) {
+                        genes.push(g.gene.as_str());
+                    }
+                    (g.gene.as_str(), g)
+                })
+                .collect()
+        })
+        .collect();
+    for gene in genes {
+        let n_guides = by_gene.iter()
+            .filter_map(|s| s.get(gene).map(|g| g.n_guides))
+            .max()
+            .unwrap_or(0);
+        write!(writer, "{}\t{}", gene, n_guides)?;
+        for s in by_gene.iter() {
+            match s.get(gene) {
+                Some(s) => write!(writer, "\t{:.6}\t{:.6e}\t{:.6}\t{:.6e}", s.score, s.pvalue, s.product, s.fdr)?,
+                None => write!(writer, "\tNA\tNA\tNA\tNA")?
+            }
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_gene_statistics() {
+    let mut table = CountTable::default();
+    let mut scores = Vec::new();
+    for idx in 0..200 {
+        table.guides.push(format!("nt_{}", idx));
+        table.genes.push("non-targeting".to_string());
+        table.controls.push(true);
+        scores.push(((idx * 37) % 200) as f64 / 1000.0 - 0.1);
+    }
+    for (gene, shift) in [("HIT", -1.0), ("NULL", 0.0)] {
+        for idx in 0..5 {
+            table.guides.push(format!("{}_{}", gene, idx));
+            table.genes.push(gene.to_string());
+            table.controls.push(false);
+            scores.push(shift + ((idx * 53) % 200) as f64 / 1000.0 - 0.1);
+        }
+    }
+    let phenotype = Phenotype { name: "gamma".to_string(), scores };
+    let stats = gene_statistics(&table, &phenotype, 3, Some(100), &mut Rng::new(42)).unwrap();
+    assert_eq!(stats.len(), 2);
+    assert_eq!(stats[0].gene, "HIT");
+    assert!(stats[0].pvalue < 0.01);
+    assert!(stats[0].product < 0.0);
+    assert!(stats[0].fdr < 0.05);
+    assert!(stats[1].fdr > stats[0].fdr);
+    assert!(gene_statistics(&table, &phenotype, 0, Some(100), &mut Rng::new(42)).is_err());
+}
+
+#[test]
+fn test_write_gene_stats_joins_genes() {
+    let mut table = CountTable::default();
+    let mut gamma = Vec::new();
+    for idx in 0..20 {
+        table.guides.push(format!("nt_{}", idx));
+        table.genes.push("non-targeting".to_string());
+        table.controls.push(true);
+        gamma.push(idx as f64 / 100.0 - 0.1);
+    }
+    for gene in ["A", "B"] {
+        for idx in 0..3 {
+            table.guides.push(format!("{}_{}", gene, idx));
+            table.genes.push(gene.to_string());
+            table.controls.push(false);
+            gamma.push(idx as f64 / 10.0 - 0.5);
+        }
+    }
+    // gene A is non-finite in tau only
+    let mut tau = gamma.clone();
+    for score in tau[20..23].iter_mut() {
+        *score = f64::NAN;
+    }
+    tau[23] = -2.0;
+    let phenotypes = vec![
+        Phenotype { name: "gamma".to_string(), scores: gamma },
+        Phenotype { name: "tau".to_string(), scores: tau }
+    ];
+    let stats: Vec<Vec<GeneStats>> = phenotypes.iter()
+        .map(|p| gene_statistics(&table, p, 3, Some(10), &mut Rng::new(1)).unwrap())
+        .collect();
+    assert_eq!(stats[1].len(), 1);
+
+    let mut out = Vec::new();
+    write_gene_stats(&mut out, &phenotypes, &stats).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].starts_with("A\t3\t"));
+    assert!(lines[1].ends_with("\tNA\tNA\tNA\tNA"));
+    let b: Vec<&str> = lines[2].split('\t').collect();
+    assert_eq!(b[0], "B");
+    assert_eq!(b[6], format!("{:.6}", stats[1][0].score));
+    assert!(stats[1][0].score < stats[0][1].score);
+}
@@ -0,0 +1,201 @@
+use std::{collections::{hash_map::Entry, HashMap}, fs::File, io::{BufRead, BufReader, Error, ErrorKind, Write}};
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Count tables of separate runs joined on their sgRNA names
+///
+/// The layout columns (Gene, and Library when it directly follows Gene) along
+/// with any named annotation columns are carried through from the first table listing a guide; every
+/// other column is a sample.
+#[derive(Debug, Clone, Default)]
+pub struct MergedTable {
+    pub metadata_names: Vec<String>,
+    pub samples: Vec<String>,
+    pub guides: Vec<String>,
+    /// Metadata values of each guide (guides x metadata columns)
+    pub metadata: Vec<Vec<String>>,
+    /// Counts of each guide (guides x samples), zero where a table lacked the guide
+    pub counts: Vec<Vec<String>>,
+    guide_index: HashMap<String, usize>
+}
+impl MergedTable {
+
+    /// Reads a count table written by screenr
+    ///
+    /// `metadata_columns` names the annotation columns of the table (as
+    /// carried through from a tabular library), which are not samples.
+    pub fn read(filename: &str, metadata_columns: &[&str]) -> Result<Self, Error> {
+        let mut lines = BufReader::new(File::open(filename)?).lines();
+        let header: Vec<String> = match lines.next() {
+            Some(line) => line?.split('\t').map(|x| x.to_string()).collect(),
+            None => return Err(invalid(format!("count table '{}' is empty", filename)))
+        };
+        if header.len() < 2 || header[0] != "sgRNA" || header[1] != "Gene" {
+            return Err(invalid(format!("'{}' is not a screenr count table (expected sgRNA and Gene columns)", filename)));
+        }
+
+        let mut rows = Vec::new();
+        for (line_idx, line) in lines.enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<String> = line.split('\t').map(|x| x.to_string()).collect();
+            if fields.len() != header.len() {
+                return Err(invalid(format!(
+                    "line {} of '{}' has {} fields but the header has {}",
+                    line_idx + 2, filename, fields.len(), header.len()
+                )));
+            }
+            rows.push(fields);
+        }
+
+        let is_sample: Vec<bool> = header.iter()
+            .enumerate()
+            .map(|(col, name)| match col {
+                0 | 1 => false,
+                2 if name == "Library" => false,
+                _ => !metadata_columns.contains(&name.as_str())
+            })
+            .collect();
+        for (line_idx, row) in rows.iter().enumerate() {
+            for (col, _) in is_sample.iter().enumerate().filter(|(_, s)| **s) {
+                if row[col].parse::<f64>().is_err() {
+                    return Err(invalid(format!(
+                        "line {} of '{}' has a non-numeric count '{}' in column '{}' (name annotation columns with --metadata-columns)",
+                        line_idx + 2, filename, row[col], header[col]
+                    )));
+                }
+            }
+        }
+
+        let mut table = Self {
+            metadata_names: header.iter().zip(is_sample.iter()).skip(1).filter(|(_, s)| !**s).map(|(h, _)| h.clone()).collect(),
+            samples: header.iter().zip(is_sample.iter()).filter(|(_, s)| **s).map(|(h, _)| h.clone()).collect(),
+            ..Self::default()
+        };
+        for mut row in rows {
+            let guide = std::mem::take(&mut row[0]);
+            if table.guide_index.contains_key(&guide) {
+                return Err(invalid(format!("sgRNA '{}' is listed more than once in '{}'", guide, filename)));
+            }
+            table.guide_index.insert(guide.clone(), table.guides.len());
+            table.guides.push(guide);
+            let (mut metadata, mut counts) = (Vec::new(), Vec::new());
+            for (value, sample) in row.into_iter().zip(is_sample.iter()).skip(1) {
+                if *sample {
+                    counts.push(value);
+                } else {
+                    metadata.push(value);
+                }
+            }
+            table.metadata.push(metadata);
+            table.counts.push(counts);
+        }
+        Ok(table)
+    }
+
+    /// Adds the samples of another table
+    ///
+    /// Guides missing from either table are given zero counts and metadata
+    /// columns missing from a guide's table are left empty.
+    pub fn merge(&mut self, other: Self) -> Result<(), Error> {
+        if let Some(name) = other.samples.iter().find(|s| self.samples.contains(s)) {
+            return Err(invalid(format!("sample '{}' is present in more than one count table", name)));
+        }
+
+        // map the other metadata columns onto ours, adding those we lack
+        let metadata_map: Vec<usize> = other.metadata_names
+            .iter()
+            .map(|name| match self.metadata_names.iter().position(|x| x == name) {
+                Some(col) => col,
+                None => {
+                    self.metadata_names.push(name.clone());
+                    self.metadata_names.len() - 1
+                }
+            })
+            .collect();
+        let num_metadata = self.metadata_names.len();
+        let num_samples = self.samples.len();
+        for metadata in self.metadata.iter_mut() {
+            metadata.resize(num_metadata, String::new());
+        }
+
+        let zeros = vec!["0".to_string(); other.samples.len()];
+        let mut seen = vec![false; self.guides.len()];
+        for ((guide, metadata), counts) in other.guides.into_iter().zip(other.metadata).zip(other.counts) {
+            let idx = match self.guide_index.entry(guide) {
+                Entry::Occupied(entry) => *entry.get(),
+                Entry::Vacant(entry) => {
+                    self.guides.push(entry.key().clone());
+                    entry.insert(self.guides.len() - 1);
+                    self.metadata.push(vec![String::new(); num_metadata]);
+                    self.counts.push(vec!["0".to_string(); num_samples]);
+                    seen.push(false);
+                    self.guides.len() - 1
+                }
+            };
+            for (value, col) in metadata.into_iter().zip(metadata_map.iter()) {
+                if self.metadata[idx][*col].is_empty() {
+                    self.metadata[idx][*col] = value;
+                }
+            }
+            self.counts[idx].extend(counts);
+            seen[idx] = true;
+        }
+        for (counts, seen) in self.counts.iter_mut().zip(seen) {
+            if !seen {
+                counts.extend(zeros.iter().cloned());
+            }
+        }
+        self.samples.extend(other.samples);
+        Ok(())
+    }
+
+    /// Writes the merged count table
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        write!(writer, "sgRNA")?;
+        for name in self.metadata_names.iter().chain(self.samples.iter()) {
+            write!(writer, "\t{}", name)?;
+        }
+        writeln!(writer)?;
+        for ((guide, metadata), counts) in self.guides.iter().zip(self.metadata.iter()).zip(self.counts.iter()) {
+            write!(writer, "{}", guide)?;
+            for value in metadata.iter().chain(counts.iter()) {
+                write!(writer, "\t{}", value)?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_merge_annotation_columns() {
+    let dir = std::env::temp_dir();
+    let filenames: Vec<String> = ["a", "b"].iter()
+        .map(|x| dir.join(format!("screenr_merge_pool_{}_{}.tsv", x, std::process::id())).to_str().unwrap().to_string())
+        .collect();
+    std::fs::write(&filenames[0], "sgRNA\tGene\tpool\ts1\ng1\tA\t1\t5\ng2\tB\t2\t7\n").unwrap();
+    std::fs::write(&filenames[1], "sgRNA\tGene\tpool\ts2\ng2\tB\t2\t3\ng3\tC\t1\t4\n").unwrap();
+
+    // numeric annotations are only samples when not named
+    let mut merged = MergedTable::read(&filenames[0], &["pool"]).unwrap();
+    merged.merge(MergedTable::read(&filenames[1], &["pool"]).unwrap()).unwrap();
+    let unnamed = MergedTable::read(&filenames[0], &[]).unwrap();
+    for filename in filenames.iter() {
+        std::fs::remove_file(filename).unwrap();
+    }
+    assert_eq!(unnamed.samples, vec!["pool", "s1"]);
+
+    assert_eq!(merged.metadata_names, vec!["Gene", "pool"]);
+    assert_eq!(merged.samples, vec!["s1", "s2"]);
+    let mut out = Vec::new();
+    merged.write(&mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "sgRNA\tGene\tpool\ts1\ts2\ng1\tA\t1\t5\t0\ng2\tB\t2\t7\t3\ng3\tC\t1\t0\t4\n"
+    );
+}
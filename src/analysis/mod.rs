@@ -0,0 +1,23 @@
+pub mod compare;
+pub mod counts;
+pub mod hits;
+pub mod merge;
+pub mod nb;
+pub mod normalize;
+pub mod phenotype;
+pub mod qc;
+pub mod report;
+pub mod rra;
+pub mod stats;
+
+pub use compare::{GuideComparison, compare_groups, write_comparisons};
+pub use counts::CountTable;
+pub use merge::MergedTable;
+pub use nb::{GuideTest, MeanVariance, nb_test, write_nb_tests};
+pub use normalize::{NormMethod, normalize, size_factors, write_size_factors};
+pub use hits::{GeneStats, gene_statistics, write_gene_stats};
+pub use phenotype::{Phenotype, write_guide_phenotypes};
+pub use qc::{Concordance, CorrelationMethod, gini, lorenz_curve, pearson, spearman};
+pub use report::{MAX_SCATTER_PAIRS, QcReport};
+pub use rra::{DEFAULT_ALPHA, GeneRra, RraScore, rank_genes, write_rra};
+pub use stats::Rng;
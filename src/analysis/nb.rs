@@ -0,0 +1,179 @@
+use std::io::{Error, Write};
+use super::CountTable;
+use super::stats::{benjamini_hochberg, nb_cdf};
+
+/// Fitted mean-variance relationship `variance = mean + k * mean^b`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeanVariance {
+    pub k: f64,
+    pub b: f64
+}
+impl MeanVariance {
+
+    /// Least squares fit of `ln(variance - mean) = ln(k) + b ln(mean)`
+    /// over the guides whose variance exceeds their mean
+    ///
+    /// Without enough overdispersed guides the fit falls back to a
+    /// Poisson relationship (k = 0).
+    pub fn fit(means: &[f64], variances: &[f64]) -> Self {
+        let points: Vec<(f64, f64)> = means
+            .iter()
+            .zip(variances)
+            .filter(|(m, v)| **m > 0.0 && *v > *m && v.is_finite())
+            .map(|(m, v)| (m.ln(), (v - m).ln()))
+            .collect();
+        let n = points.len() as f64;
+        if points.len() < 2 {
+            return Self { k: 0.0, b: 1.0 };
+        }
+        let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+        let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+        let sxx: f64 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+        let sxy: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+        let b = if sxx > 0.0 { sxy / sxx } else { 0.0 };
+        Self { k: (mean_y - b * mean_x).exp(), b }
+    }
+
+    /// Modeled variance at a mean
+    pub fn variance(&self, mean: f64) -> f64 {
+        mean + self.k * mean.powf(self.b)
+    }
+}
+
+/// Negative binomial test of a single guide
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GuideTest {
+    pub control_mean: f64,
+    pub treatment_mean: f64,
+    pub control_var: f64,
+    pub adj_var: f64,
+    pub log2fc: f64,
+    pub p_low: f64,
+    pub p_high: f64,
+    pub p_twosided: f64,
+    pub fdr: f64
+}
+
+fn mean_var(values: &[f64]) -> (f64, f64) {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    if values.len() < 2 {
+        return (mean, f64::NAN);
+    }
+    let var = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (mean, var)
+}
+
+/// Tests every guide for depletion / enrichment between groups of samples
+///
+/// The variance of each guide is estimated across the reference replicates
+/// (or across all samples when there is a single reference sample), a
+/// mean-variance model is fit over all guides and the treatment mean is
+/// compared to a negative binomial with the reference mean and the larger
+/// of the modeled and observed variance.
+pub fn nb_test(table: &CountTable, normalized: &[f64], reference: &[usize], treatment: &[usize]) -> (MeanVariance, Vec<GuideTest>) {
+    let n = table.num_samples();
+    let variance_samples: Vec<usize> = if reference.len() > 1 {
+        reference.to_vec()
+    } else {
+        reference.iter().chain(treatment.iter()).copied().collect()
+    };
+
+    let mut control_means = Vec::with_capacity(table.num_guides());
+    let mut control_vars = Vec::with_capacity(table.num_guides());
+    for row in normalized.chunks(n) {
+        let reference_values: Vec<f64> = reference.iter().map(|s| row[*s]).collect();
+        let variance_values: Vec<f64> = variance_samples.iter().map(|s| row[*s]).collect();
+        control_means.push(mean_var(&reference_values).0);
+        control_vars.push(mean_var(&variance_values).1);
+    }
+    let model = MeanVariance::fit(&control_means, &control_vars);
+
+    let mut tests: Vec<GuideTest> = normalized
+        .chunks(n)
+        .enumerate()
+        .map(|(guide, row)| {
+            let control_mean = control_means[guide];
+            let control_var = control_vars[guide];
+            let treatment_mean = treatment.iter().map(|s| row[*s]).sum::<f64>() / treatment.len() as f64;
+            let adj_var = match control_var.is_finite() {
+                true => model.variance(control_mean).max(control_var),
+                false => model.variance(control_mean)
+            };
+            let k = treatment_mean.round();
+            let p_low = nb_cdf(k, control_mean, adj_var);
+            let p_high = 1.0 - nb_cdf(k - 1.0, control_mean, adj_var);
+            let log_ref = reference.iter().map(|s| row[*s].log2()).sum::<f64>() / reference.len() as f64;
+            let log_trt = treatment.iter().map(|s| row[*s].log2()).sum::<f64>() / treatment.len() as f64;
+            GuideTest {
+                control_mean,
+                treatment_mean,
+                control_var,
+                adj_var,
+                log2fc: log_trt - log_ref,
+                p_low: p_low.clamp(0.0, 1.0),
+                p_high: p_high.clamp(0.0, 1.0),
+                p_twosided: (2.0 * p_low.min(p_high)).clamp(0.0, 1.0),
+                fdr: 1.0
+            }
+        })
+        .collect();
+
+    let pvalues: Vec<f64> = tests.iter().map(|t| t.p_twosided).collect();
+    for (test, fdr) in tests.iter_mut().zip(benjamini_hochberg(&pvalues)) {
+        test.fdr = fdr;
+    }
+    (model, tests)
+}
+
+/// Writes negative binomial guide tests as a TSV
+pub fn write_nb_tests<W: Write>(writer: &mut W, table: &CountTable, tests: &[GuideTest]) -> Result<(), Error> {
+    writeln!(writer, "sgRNA\tGene\tControlMean\tTreatmentMean\tControlVar\tAdjVar\tLog2FC\tp_low\tp_high\tp_twosided\tFDR")?;
+    for (guide, t) in tests.iter().enumerate() {
+        writeln!(
+            writer,
+            "{}\t{}\t{:.4}\t{:.4}\t{:.4}\t{:.4}\t{:.6}\t{:.6e}\t{:.6e}\t{:.6e}\t{:.6}",
+            table.guides[guide], table.genes[guide],
+            t.control_mean, t.treatment_mean, t.control_var, t.adj_var, t.log2fc,
+            t.p_low, t.p_high, t.p_twosided, t.fdr
+        )?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_mean_variance_fit() {
+    let means = [10.0, 100.0, 1000.0];
+    let variances: Vec<f64> = means.iter().map(|m| m + 0.5 * m * m).collect();
+    let model = MeanVariance::fit(&means, &variances);
+    assert!((model.k - 0.5).abs() < 1e-9);
+    assert!((model.b - 2.0).abs() < 1e-9);
+    assert_eq!(MeanVariance::fit(&[1.0], &[0.5]), MeanVariance { k: 0.0, b: 1.0 });
+}
+
+#[test]
+fn test_nb_test() {
+    let mut table = CountTable {
+        samples: vec!["r1".into(), "r2".into(), "t1".into(), "t2".into()],
+        ..CountTable::default()
+    };
+    for idx in 0..50 {
+        let base = 100.0 + idx as f64 * 10.0;
+        table.guides.push(format!("g{}", idx));
+        table.genes.push(format!("G{}", idx));
+        table.controls.push(false);
+        table.counts.extend([base * 0.9, base * 1.1, base * 0.95, base * 1.05]);
+    }
+    table.guides.push("down".into());
+    table.genes.push("DOWN".into());
+    table.controls.push(false);
+    table.counts.extend([500.0, 520.0, 20.0, 25.0]);
+
+    let (_, tests) = nb_test(&table, &table.counts, &[0, 1], &[2, 3]);
+    let down = tests.last().unwrap();
+    assert!(down.p_low < 1e-6);
+    assert!(down.p_high > 0.99);
+    assert!(down.fdr < 0.01);
+    assert!(down.log2fc < -4.0);
+    assert!(tests[..50].iter().all(|t| t.fdr > 0.05));
+}
@@ -0,0 +1,143 @@
+use std::{fs::File, io::{BufWriter, Error, ErrorKind, Write}, str::FromStr};
+use super::CountTable;
+
+/// Methods of estimating per-sample size factors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NormMethod {
+    /// Counts per million reads assigned to guides
+    Cpm,
+    /// DESeq-style median of ratios to the per-guide geometric mean
+    MedianRatio,
+    /// Median of ratios computed on control guides only
+    Control
+}
+impl FromStr for NormMethod {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cpm" => Ok(Self::Cpm),
+            "median-ratio" | "median" => Ok(Self::MedianRatio),
+            "control" => Ok(Self::Control),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown normalization method '{}' (expected cpm, median-ratio or control)", s)
+            ))
+        }
+    }
+}
+
+/// Median of a set of values (reorders them)
+pub fn median(values: &mut [f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        Some((values[mid - 1] + values[mid]) / 2.0)
+    } else {
+        Some(values[mid])
+    }
+}
+
+/// Median-ratio size factors over the guides passing `keep`
+///
+/// Guides with a zero (pseudo)count in any sample have no defined
+/// geometric mean and are skipped.
+fn median_ratio<F: Fn(usize) -> bool>(table: &CountTable, pseudocount: f64, keep: F) -> Result<Vec<f64>, Error> {
+    let n = table.num_samples();
+    let mut ratios = vec![Vec::new(); n];
+    for guide in (0..table.num_guides()).filter(|g| keep(*g)) {
+        let row: Vec<f64> = table.row(guide).iter().map(|c| c + pseudocount).collect();
+        if row.iter().any(|c| *c <= 0.0) {
+            continue;
+        }
+        let log_mean = row.iter().map(|c| c.ln()).sum::<f64>() / n as f64;
+        for (sample, c) in row.iter().enumerate() {
+            ratios[sample].push(c.ln() - log_mean);
+        }
+    }
+    ratios.iter_mut()
+        .map(|r| {
+            median(r)
+                .map(f64::exp)
+                .ok_or_else(|| Error::new(
+                    ErrorKind::InvalidData,
+                    "no guides with nonzero counts in every sample to estimate size factors from (consider a pseudocount)"
+                ))
+        })
+        .collect()
+}
+
+/// Estimates the size factor of each sample
+pub fn size_factors(table: &CountTable, method: NormMethod, pseudocount: f64) -> Result<Vec<f64>, Error> {
+    match method {
+        NormMethod::Cpm => {
+            Ok((0..table.num_samples())
+                .map(|s| {
+                    let total: f64 = table.column(s).map(|c| c + pseudocount).sum();
+                    (total / 1e6).max(f64::MIN_POSITIVE)
+                })
+                .collect())
+        },
+        NormMethod::MedianRatio => median_ratio(table, pseudocount, |_| true),
+        NormMethod::Control => {
+            if !table.controls.iter().any(|x| *x) {
+                return Err(Error::new(ErrorKind::InvalidData, "no control guides found for control normalization"));
+            }
+            median_ratio(table, pseudocount, |g| table.controls[g])
+        }
+    }
+}
+
+/// Divides the (pseudo)counts of each sample by its size factor
+pub fn normalize(table: &CountTable, size_factors: &[f64], pseudocount: f64) -> Vec<f64> {
+    let n = table.num_samples();
+    table.counts
+        .iter()
+        .enumerate()
+        .map(|(idx, c)| (c + pseudocount) / size_factors[idx % n])
+        .collect()
+}
+
+/// Writes the size factor of each sample
+pub fn write_size_factors(filename: &str, table: &CountTable, size_factors: &[f64]) -> Result<(), Error> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    writeln!(writer, "Sample\tSizeFactor")?;
+    for (sample, sf) in table.samples.iter().zip(size_factors) {
+        writeln!(writer, "{}\t{:.6}", sample, sf)?;
+    }
+    writer.flush()
+}
+
+#[test]
+fn test_size_factors() {
+    let table = CountTable {
+        guides: vec!["a".into(), "b".into(), "c".into()],
+        genes: vec!["A".into(), "B".into(), "non-targeting".into()],
+        controls: vec![false, false, true],
+        samples: vec!["s1".into(), "s2".into()],
+        counts: vec![
+            10.0, 20.0,
+            30.0, 60.0,
+            5.0, 20.0
+        ]
+    };
+    let cpm = size_factors(&table, NormMethod::Cpm, 0.0).unwrap();
+    assert!((cpm[0] - 45e-6).abs() < 1e-12);
+    assert_eq!(normalize(&table, &cpm, 0.0)[1], 20.0 / 100e-6);
+
+    // every guide is twice as abundant in s2 except the control
+    let mr = size_factors(&table, NormMethod::MedianRatio, 0.0).unwrap();
+    assert!((mr[1] / mr[0] - 2.0).abs() < 1e-9);
+
+    let ctrl = size_factors(&table, NormMethod::Control, 0.0).unwrap();
+    assert!((ctrl[1] / ctrl[0] - 4.0).abs() < 1e-9);
+
+    let norm = normalize(&table, &ctrl, 0.0);
+    assert!((norm[4] - norm[5]).abs() < 1e-9);
+    assert!("tmm".parse::<NormMethod>().is_err());
+
+    // NaN sorts last rather than panicking
+    assert_eq!(median(&mut [3.0, f64::NAN, 1.0]), Some(3.0));
+}
@@ -0,0 +1,136 @@
+use std::{collections::HashMap, io::{Error, ErrorKind, Write}};
+use super::{CountTable, GuideComparison};
+use super::normalize::median;
+
+/// A guide-level phenotype (e.g. gamma, tau or rho) of every guide in a table
+#[derive(Debug, Clone)]
+pub struct Phenotype {
+    pub name: String,
+    pub scores: Vec<f64>
+}
+impl Phenotype {
+
+    /// Scores guides by their log2 fold change relative to the median control
+    /// guide, divided by the number of doublings separating the samples
+    pub fn from_comparisons(name: &str, table: &CountTable, comparisons: &[GuideComparison], doublings: f64) -> Result<Self, Error> {
+        if doublings == 0.0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} phenotype requires a nonzero number of doublings", name)
+            ));
+        }
+        let mut control_lfc: Vec<f64> = comparisons
+            .iter()
+            .zip(table.controls.iter())
+            .filter(|(c, is_control)| **is_control && c.log2fc.is_finite())
+            .map(|(c, _)| c.log2fc)
+            .collect();
+        let control_median = median(&mut control_lfc)
+            .ok_or_else(|| Error::new(
+                ErrorKind::InvalidData,
+                "no control guides found to center phenotypes on"
+            ))?;
+        Ok(Self {
+            name: name.to_string(),
+            scores: comparisons
+                .iter()
+                .map(|c| (c.log2fc - control_median) / doublings)
+                .collect()
+        })
+    }
+
+    /// Finite phenotypes of the guides of each gene
+    ///
+    /// Control guides are not grouped. Genes are returned in order of
+    /// their first appearance in the table.
+    pub fn gene_groups(&self, table: &CountTable) -> Vec<(String, Vec<f64>)> {
+        let mut order = Vec::new();
+        let mut by_gene: HashMap<&str, Vec<f64>> = HashMap::new();
+        for (guide, score) in self.scores.iter().enumerate() {
+            if table.controls[guide] || !score.is_finite() {
+                continue;
+            }
+            let gene = table.genes[guide].as_str();
+            by_gene.entry(gene)
+                .or_insert_with(|| {
+                    order.push(gene);
+                    Vec::new()
+                })
+                .push(*score);
+        }
+        order.into_iter()
+            .map(|gene| (gene.to_string(), by_gene.remove(gene).unwrap()))
+            .collect()
+    }
+
+    /// Finite phenotypes of the control guides
+    pub fn control_scores(&self, table: &CountTable) -> Vec<f64> {
+        self.scores
+            .iter()
+            .zip(table.controls.iter())
+            .filter(|(score, is_control)| **is_control && score.is_finite())
+            .map(|(score, _)| *score)
+            .collect()
+    }
+
+    /// Gene-level phenotypes as the mean of the `top_n` strongest guides of each gene
+    pub fn gene_scores(&self, table: &CountTable, top_n: usize) -> Vec<(String, usize, f64)> {
+        self.gene_groups(table)
+            .into_iter()
+            .map(|(gene, scores)| {
+                let n_guides = scores.len();
+                (gene, n_guides, mean_top(&top_guides(scores, top_n)))
+            })
+            .collect()
+    }
+}
+
+/// The `top_n` phenotypes of largest magnitude
+pub fn top_guides(mut scores: Vec<f64>, top_n: usize) -> Vec<f64> {
+    scores.sort_by(|a, b| b.abs().total_cmp(&a.abs()));
+    scores.truncate(top_n);
+    scores
+}
+
+/// Mean of a set of phenotypes
+pub fn mean_top(scores: &[f64]) -> f64 {
+    scores.iter().sum::<f64>() / scores.len() as f64
+}
+
+/// Writes guide-level phenotypes as a TSV
+pub fn write_guide_phenotypes<W: Write>(writer: &mut W, table: &CountTable, phenotypes: &[Phenotype]) -> Result<(), Error> {
+    write!(writer, "sgRNA\tGene")?;
+    for p in phenotypes {
+        write!(writer, "\t{}", p.name)?;
+    }
+    writeln!(writer)?;
+    for guide in 0..table.num_guides() {
+        write!(writer, "{}\t{}", table.guides[guide], table.genes[guide])?;
+        for p in phenotypes {
+            write!(writer, "\t{:.6}", p.scores[guide])?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_phenotype() {
+    let table = CountTable {
+        guides: vec!["a1".into(), "a2".into(), "a3".into(), "a4".into(), "nt1".into(), "nt2".into()],
+        genes: vec!["A".into(), "A".into(), "A".into(), "A".into(), "non-targeting".into(), "non-targeting".into()],
+        controls: vec![false, false, false, false, true, true],
+        samples: Vec::new(),
+        counts: Vec::new()
+    };
+    let comparisons: Vec<GuideComparison> = [-3.0, -5.0, 1.0, -7.0, 1.0, 1.0]
+        .iter()
+        .map(|lfc| GuideComparison { reference: 0.0, treatment: 0.0, log2fc: *lfc })
+        .collect();
+    let gamma = Phenotype::from_comparisons("gamma", &table, &comparisons, 2.0).unwrap();
+    assert_eq!(gamma.scores, vec![-2.0, -3.0, 0.0, -4.0, 0.0, 0.0]);
+
+    let genes = gamma.gene_scores(&table, 3);
+    assert_eq!(genes, vec![("A".to_string(), 4, -3.0)]);
+    assert!(Phenotype::from_comparisons("gamma", &table, &comparisons, 0.0).is_err());
+}
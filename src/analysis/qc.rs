@@ -0,0 +1,229 @@
+use std::{io::{Error, ErrorKind, Write}, str::FromStr};
+use super::CountTable;
+use super::stats::rank;
+
+/// Correlation method used for the matrix output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CorrelationMethod {
+    Pearson,
+    Spearman
+}
+impl FromStr for CorrelationMethod {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pearson" => Ok(Self::Pearson),
+            "spearman" => Ok(Self::Spearman),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown correlation method '{}' (expected pearson or spearman)", s)
+            ))
+        }
+    }
+}
+
+/// Pearson correlation of two equally sized samples
+pub fn pearson(x: &[f64], y: &[f64]) -> f64 {
+    let n = x.len() as f64;
+    let mean_x = x.iter().sum::<f64>() / n;
+    let mean_y = y.iter().sum::<f64>() / n;
+    let (mut sxy, mut sxx, mut syy) = (0.0, 0.0, 0.0);
+    for (a, b) in x.iter().zip(y) {
+        sxy += (a - mean_x) * (b - mean_y);
+        sxx += (a - mean_x).powi(2);
+        syy += (b - mean_y).powi(2);
+    }
+    sxy / (sxx * syy).sqrt()
+}
+
+/// Spearman rank correlation of two equally sized samples
+pub fn spearman(x: &[f64], y: &[f64]) -> f64 {
+    pearson(&rank(x), &rank(y))
+}
+
+/// Gini coefficient of guide counts (0 = perfectly even, 1 = a single guide)
+pub fn gini(counts: &[f64]) -> f64 {
+    let mut sorted = counts.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let n = sorted.len() as f64;
+    let total: f64 = sorted.iter().sum();
+    if total <= 0.0 {
+        return 0.0;
+    }
+    let weighted: f64 = sorted.iter()
+        .enumerate()
+        .map(|(i, x)| (i as f64 + 1.0) * x)
+        .sum();
+    (2.0 * weighted) / (n * total) - (n + 1.0) / n
+}
+
+/// Cumulative fraction of reads held by the least abundant guides
+///
+/// Returns `points + 1` evenly spaced (guide fraction, read fraction) pairs
+/// from (0, 0) to (1, 1).
+pub fn lorenz_curve(counts: &[f64], points: usize) -> Vec<(f64, f64)> {
+    let mut sorted = counts.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let total = sorted.iter().sum::<f64>().max(f64::MIN_POSITIVE);
+    let mut cumulative = Vec::with_capacity(sorted.len() + 1);
+    cumulative.push(0.0);
+    for x in sorted.iter() {
+        cumulative.push(cumulative.last().unwrap() + x / total);
+    }
+    (0..=points)
+        .map(|p| {
+            let frac = p as f64 / points as f64;
+            let idx = (frac * sorted.len() as f64).round() as usize;
+            (frac, cumulative[idx])
+        })
+        .collect()
+}
+
+/// Pairwise sample correlations of log-normalized guide counts
+#[derive(Debug, Clone)]
+pub struct Concordance {
+    pub samples: Vec<String>,
+    /// Number of guides with counts in at least one sample
+    pub num_guides: usize,
+    /// Row-major samples x samples matrices
+    pub pearson: Vec<f64>,
+    pub spearman: Vec<f64>
+}
+impl Concordance {
+
+    /// Correlates every pair of samples on log2(CPM + 1) of the guides
+    /// observed in at least one sample
+    pub fn new(table: &CountTable) -> Self {
+        let n = table.num_samples();
+        let totals: Vec<f64> = (0..n)
+            .map(|s| table.column(s).sum::<f64>().max(1.0))
+            .collect();
+        let observed: Vec<usize> = (0..table.num_guides())
+            .filter(|g| table.row(*g).iter().any(|c| *c > 0.0))
+            .collect();
+        let columns: Vec<Vec<f64>> = (0..n)
+            .map(|s| {
+                observed.iter()
+                    .map(|g| (table.row(*g)[s] / totals[s] * 1e6 + 1.0).log2())
+                    .collect()
+            })
+            .collect();
+
+        let mut pearson_matrix = vec![1.0; n * n];
+        let mut spearman_matrix = vec![1.0; n * n];
+        for i in 0..n {
+            for j in (i + 1)..n {
+                let p = pearson(&columns[i], &columns[j]);
+                let s = spearman(&columns[i], &columns[j]);
+                pearson_matrix[i * n + j] = p;
+                pearson_matrix[j * n + i] = p;
+                spearman_matrix[i * n + j] = s;
+                spearman_matrix[j * n + i] = s;
+            }
+        }
+        Self {
+            samples: table.samples.clone(),
+            num_guides: observed.len(),
+            pearson: pearson_matrix,
+            spearman: spearman_matrix
+        }
+    }
+
+    /// Correlation matrix of a method
+    pub fn matrix(&self, method: CorrelationMethod) -> &[f64] {
+        match method {
+            CorrelationMethod::Pearson => &self.pearson,
+            CorrelationMethod::Spearman => &self.spearman
+        }
+    }
+
+    /// Mean correlation of each sample with the other members of its replicate group
+    ///
+    /// Samples without other replicates have no concordance (NaN).
+    pub fn replicate_concordance(&self, groups: &[&str], method: CorrelationMethod) -> Vec<f64> {
+        let n = self.samples.len();
+        let matrix = self.matrix(method);
+        (0..n)
+            .map(|i| {
+                let others: Vec<f64> = (0..n)
+                    .filter(|j| *j != i && groups[*j] == groups[i])
+                    .map(|j| matrix[i * n + j])
+                    .collect();
+                others.iter().sum::<f64>() / others.len() as f64
+            })
+            .collect()
+    }
+
+    /// Writes the correlations of every sample pair
+    pub fn write_pairs<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let n = self.samples.len();
+        writeln!(writer, "SampleA\tSampleB\tPearson\tSpearman")?;
+        for i in 0..n {
+            for j in (i + 1)..n {
+                writeln!(
+                    writer,
+                    "{}\t{}\t{:.6}\t{:.6}",
+                    self.samples[i], self.samples[j], self.pearson[i * n + j], self.spearman[i * n + j]
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a samples x samples correlation matrix
+    pub fn write_matrix<W: Write>(&self, writer: &mut W, method: CorrelationMethod) -> Result<(), Error> {
+        let n = self.samples.len();
+        let matrix = self.matrix(method);
+        write!(writer, "Sample")?;
+        for s in self.samples.iter() {
+            write!(writer, "\t{}", s)?;
+        }
+        writeln!(writer)?;
+        for i in 0..n {
+            write!(writer, "{}", self.samples[i])?;
+            for j in 0..n {
+                write!(writer, "\t{:.6}", matrix[i * n + j])?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_gini() {
+    assert!(gini(&[5.0, 5.0, 5.0, 5.0]).abs() < 1e-12);
+    assert!((gini(&[0.0, 0.0, 0.0, 8.0]) - 0.75).abs() < 1e-12);
+    assert_eq!(gini(&[0.0, 0.0]), 0.0);
+
+    let curve = lorenz_curve(&[0.0, 0.0, 0.0, 8.0], 4);
+    assert_eq!(curve, vec![(0.0, 0.0), (0.25, 0.0), (0.5, 0.0), (0.75, 0.0), (1.0, 1.0)]);
+}
+
+#[test]
+fn test_concordance() {
+    assert!((pearson(&[1.0, 2.0, 3.0], &[2.0, 4.0, 6.0]) - 1.0).abs() < 1e-12);
+    assert!((spearman(&[1.0, 2.0, 3.0], &[1.0, 10.0, 100.0]) - 1.0).abs() < 1e-12);
+    assert!((spearman(&[1.0, 2.0, 3.0], &[3.0, 2.0, 1.0]) + 1.0).abs() < 1e-12);
+
+    let table = CountTable {
+        guides: (0..4).map(|x| x.to_string()).collect(),
+        genes: (0..4).map(|x| x.to_string()).collect(),
+        controls: vec![false; 4],
+        samples: vec!["a1".into(), "a2".into(), "b1".into()],
+        counts: vec![
+            10.0, 20.0, 40.0,
+            100.0, 200.0, 10.0,
+            1000.0, 2000.0, 20.0,
+            0.0, 0.0, 0.0
+        ]
+    };
+    let concordance = Concordance::new(&table);
+    assert_eq!(concordance.num_guides, 3);
+    assert!((concordance.spearman[1] - 1.0).abs() < 1e-12);
+    assert!(concordance.pearson[2] < 0.0);
+
+    let replicates = concordance.replicate_concordance(&["a", "a", "b"], CorrelationMethod::Spearman);
+    assert!((replicates[0] - 1.0).abs() < 1e-12);
+    assert!(replicates[2].is_nan());
+}
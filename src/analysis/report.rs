@@ -0,0 +1,372 @@
+use std::{fmt::Write as FmtWrite, fs::File, io::{BufWriter, Error, Write}};
+use crate::crispr::{SampleSheet, SampleStats};
+use super::CountTable;
+use super::qc::{Concordance, gini, lorenz_curve};
+
+/// Largest number of sample pairs drawn as replicate scatter plots
+pub const MAX_SCATTER_PAIRS: usize = 12;
+
+/// Largest number of guides drawn in a single scatter plot
+const MAX_SCATTER_POINTS: usize = 5000;
+
+/// Number of bins of the read count histograms
+const HISTOGRAM_BINS: usize = 30;
+
+/// Number of points drawn along each Lorenz curve
+const LORENZ_POINTS: usize = 100;
+
+/// Colors cycled through for samples
+const PALETTE: [&str; 10] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd",
+    "#8c564b", "#e377c2", "#7f7f7f", "#bcbd22", "#17becf"
+];
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+h1{font-size:1.6em}h2{font-size:1.2em;margin-top:2em;border-bottom:1px solid #ccc}\
+table{border-collapse:collapse;font-size:0.9em}td,th{padding:4px 10px;border:1px solid #ddd;text-align:right}\
+th{background:#f4f4f4}td:first-child,th:first-child{text-align:left}\
+.plots{display:flex;flex-wrap:wrap;gap:12px}svg{background:#fff;border:1px solid #eee}\
+svg text{font-size:10px;fill:#333}.note{color:#666;font-size:0.9em}";
+
+/// A self-contained HTML quality control report of a count table
+///
+/// All plots are rendered as inline SVG so that the report can be opened
+/// offline and shared as a single file.
+pub struct QcReport<'a> {
+    table: &'a CountTable,
+    mapping: Option<&'a [SampleStats]>,
+    sheet: Option<&'a SampleSheet>,
+    concordance: Concordance,
+    pairs: Vec<(usize, usize)>
+}
+impl<'a> QcReport<'a> {
+
+    /// Prepares a report plotting every sample pair against each other
+    pub fn new(table: &'a CountTable) -> Self {
+        let n = table.num_samples();
+        let pairs = (0..n)
+            .flat_map(|i| ((i + 1)..n).map(move |j| (i, j)))
+            .take(MAX_SCATTER_PAIRS)
+            .collect();
+        Self { table, mapping: None, sheet: None, concordance: Concordance::new(table), pairs }
+    }
+
+    /// Adds the read matching statistics of each sample
+    pub fn with_mapping(mut self, mapping: &'a [SampleStats]) -> Self {
+        self.mapping = Some(mapping);
+        self
+    }
+
+    /// Adds the metadata of each sample, plotting replicates of a condition against each other
+    pub fn with_sheet(self, sheet: &'a SampleSheet) -> Self {
+        let mut report = self.with_groups(&sheet.groups());
+        report.sheet = Some(sheet);
+        report
+    }
+
+    /// Restricts the scatter plots to pairs of samples of the same replicate group
+    pub fn with_groups(mut self, groups: &[&str]) -> Self {
+        let n = self.table.num_samples();
+        self.pairs = (0..n)
+            .flat_map(|i| ((i + 1)..n).map(move |j| (i, j)))
+            .filter(|(i, j)| groups[*i] == groups[*j])
+            .take(MAX_SCATTER_PAIRS)
+            .collect();
+        self
+    }
+
+    /// Writes the report to a file
+    pub fn save(&self, filename: &str) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Writes the report as a single HTML document
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let mut html = String::new();
+        html.push_str("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>screenr QC report</title>");
+        let _ = writeln!(html, "<style>{}</style></head><body>", STYLE);
+        let _ = writeln!(
+            html,
+            "<h1>screenr QC report</h1><p class=\"note\">{} samples, {} guides ({} with reads in at least one sample)</p>",
+            self.table.num_samples(), self.table.num_guides(), self.concordance.num_guides
+        );
+        self.summary_section(&mut html);
+        self.histogram_section(&mut html);
+        self.lorenz_section(&mut html);
+        self.scatter_section(&mut html);
+        self.correlation_section(&mut html);
+        html.push_str("</body></html>\n");
+        writer.write_all(html.as_bytes())
+    }
+
+    fn summary_section(&self, html: &mut String) {
+        html.push_str("<h2>Samples</h2><table><tr><th>Sample</th>");
+        if self.sheet.is_some() {
+            html.push_str("<th>Condition</th><th>Replicate</th><th>Barcode</th>");
+        }
+        if self.mapping.is_some() {
+            html.push_str("<th>Reads</th><th>Guide Matches</th><th>Mapping Rate</th><th>Control Fraction</th>");
+        }
+        html.push_str("<th>Counted Reads</th><th>Zero-count Guides</th><th>Gini</th></tr>\n");
+        for (s, name) in self.table.samples.iter().enumerate() {
+            let counts: Vec<f64> = self.table.column(s).collect();
+            let zeros = counts.iter().filter(|x| **x == 0.0).count();
+            let _ = write!(html, "<tr><td>{}</td>", escape(name));
+            if let Some(sample) = self.sheet.and_then(|sheet| sheet.samples.get(s)) {
+                for value in [&sample.condition, &sample.replicate, &sample.barcode] {
+                    let _ = write!(html, "<td>{}</td>", escape(value.as_deref().unwrap_or("")));
+                }
+            }
+            if let Some(stats) = self.mapping.and_then(|m| m.get(s)) {
+                let _ = write!(
+                    html,
+                    "<td>{}</td><td>{}</td><td>{:.2}%</td><td>{:.2}%</td>",
+                    stats.num_total, stats.num_assigned,
+                    100.0 * stats.mapping_rate(), 100.0 * stats.control_fraction()
+                );
+            }
+            let _ = writeln!(
+                html,
+                "<td>{}</td><td>{} ({:.2}%)</td><td>{:.4}</td></tr>",
+                counts.iter().sum::<f64>(), zeros,
+                100.0 * zeros as f64 / counts.len().max(1) as f64, gini(&counts)
+            );
+        }
+        html.push_str("</table>\n");
+    }
+
+    fn histogram_section(&self, html: &mut String) {
+        html.push_str("<h2>Read count distributions</h2><div class=\"plots\">\n");
+        let columns: Vec<Vec<f64>> = (0..self.table.num_samples())
+            .map(|s| self.table.column(s).map(|x| (x + 1.0).log10()).collect())
+            .collect();
+        let max_value = columns.iter()
+            .flatten()
+            .fold(0.0f64, |acc, x| acc.max(*x))
+            .max(1.0);
+        let width = max_value / HISTOGRAM_BINS as f64;
+        for (s, column) in columns.iter().enumerate() {
+            let mut bins = [0usize; HISTOGRAM_BINS];
+            for x in column.iter() {
+                bins[((x / width) as usize).min(HISTOGRAM_BINS - 1)] += 1;
+            }
+            let frame = Frame::new(300.0, 200.0, max_value, *bins.iter().max().unwrap() as f64);
+            frame.open(html, &self.table.samples[s], "log10(count + 1)", "guides");
+            for (b, n) in bins.iter().enumerate() {
+                let x0 = frame.x(b as f64 * width);
+                let x1 = frame.x((b + 1) as f64 * width);
+                let y = frame.y(*n as f64);
+                let _ = write!(
+                    html,
+                    "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\"/>",
+                    x0, y, (x1 - x0 - 0.5).max(0.5), frame.y(0.0) - y, color(s)
+                );
+            }
+            html.push_str("</svg>\n");
+        }
+        html.push_str("</div>\n");
+    }
+
+    fn lorenz_section(&self, html: &mut String) {
+        html.push_str("<h2>Cumulative guide distributions</h2>\n");
+        html.push_str("<p class=\"note\">Fraction of reads held by the least abundant fraction of guides; the diagonal is a perfectly even library.</p>\n");
+        let frame = Frame::new(420.0, 360.0, 1.0, 1.0);
+        frame.open(html, "Lorenz curves", "fraction of guides", "fraction of reads");
+        let _ = write!(
+            html,
+            "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"#aaa\" stroke-dasharray=\"4\"/>",
+            frame.x(0.0), frame.y(0.0), frame.x(1.0), frame.y(1.0)
+        );
+        for s in 0..self.table.num_samples() {
+            let counts: Vec<f64> = self.table.column(s).collect();
+            let points: Vec<String> = lorenz_curve(&counts, LORENZ_POINTS)
+                .iter()
+                .map(|(x, y)| format!("{:.1},{:.1}", frame.x(*x), frame.y(*y)))
+                .collect();
+            let _ = write!(
+                html,
+                "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\"/>",
+                points.join(" "), color(s)
+            );
+            let _ = write!(
+                html,
+                "<text x=\"{:.1}\" y=\"{:.1}\" fill=\"{}\">{}</text>",
+                frame.x(0.05), frame.y(0.95) + 12.0 * s as f64, color(s), escape(&self.table.samples[s])
+            );
+        }
+        html.push_str("</svg>\n");
+    }
+
+    fn scatter_section(&self, html: &mut String) {
+        html.push_str("<h2>Replicate scatter plots</h2>\n");
+        if self.pairs.is_empty() {
+            html.push_str("<p class=\"note\">No sample pairs to compare.</p>\n");
+            return;
+        }
+        let _ = writeln!(
+            html,
+            "<p class=\"note\">log2(CPM + 1) of guides with reads in either sample (at most {} guides per plot).</p><div class=\"plots\">",
+            MAX_SCATTER_POINTS
+        );
+        let n = self.table.num_samples();
+        let totals: Vec<f64> = (0..n)
+            .map(|s| self.table.column(s).sum::<f64>().max(1.0))
+            .collect();
+        for (i, j) in self.pairs.iter() {
+            let points: Vec<(f64, f64)> = (0..self.table.num_guides())
+                .map(|g| self.table.row(g))
+                .filter(|row| row[*i] > 0.0 || row[*j] > 0.0)
+                .map(|row| (
+                    (row[*i] / totals[*i] * 1e6 + 1.0).log2(),
+                    (row[*j] / totals[*j] * 1e6 + 1.0).log2()
+                ))
+                .collect();
+            let stride = points.len().div_ceil(MAX_SCATTER_POINTS).max(1);
+            let max_value = points.iter()
+                .fold(1.0f64, |acc, (x, y)| acc.max(*x).max(*y));
+            let frame = Frame::new(300.0, 300.0, max_value, max_value);
+            let title = format!(
+                "{} vs {} (r = {:.3})",
+                self.table.samples[*i], self.table.samples[*j], self.concordance.pearson[i * n + j]
+            );
+            frame.open(html, &title, &self.table.samples[*i], &self.table.samples[*j]);
+            for (x, y) in points.iter().step_by(stride) {
+                let _ = write!(
+                    html,
+                    "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"1.2\" fill=\"#1f77b4\" fill-opacity=\"0.4\"/>",
+                    frame.x(*x), frame.y(*y)
+                );
+            }
+            html.push_str("</svg>\n");
+        }
+        html.push_str("</div>\n");
+    }
+
+    fn correlation_section(&self, html: &mut String) {
+        html.push_str("<h2>Sample correlations (Pearson)</h2><table><tr><th>Sample</th>");
+        for name in self.concordance.samples.iter() {
+            let _ = write!(html, "<th>{}</th>", escape(name));
+        }
+        html.push_str("</tr>\n");
+        let n = self.concordance.samples.len();
+        for i in 0..n {
+            let _ = write!(html, "<tr><td>{}</td>", escape(&self.concordance.samples[i]));
+            for j in 0..n {
+                let r = self.concordance.pearson[i * n + j];
+                // shade from white (r <= 0) to blue (r = 1)
+                let shade = 255.0 - 120.0 * r.clamp(0.0, 1.0);
+                let _ = write!(
+                    html,
+                    "<td style=\"background:rgb({:.0},{:.0},255)\">{:.3}</td>",
+                    shade, shade, r
+                );
+            }
+            html.push_str("</tr>\n");
+        }
+        html.push_str("</table>\n");
+    }
+}
+
+/// Maps data coordinates onto an SVG plot area
+struct Frame {
+    width: f64,
+    height: f64,
+    x_max: f64,
+    y_max: f64
+}
+impl Frame {
+    const LEFT: f64 = 50.0;
+    const RIGHT: f64 = 12.0;
+    const TOP: f64 = 24.0;
+    const BOTTOM: f64 = 36.0;
+
+    fn new(width: f64, height: f64, x_max: f64, y_max: f64) -> Self {
+        Self { width, height, x_max: x_max.max(f64::MIN_POSITIVE), y_max: y_max.max(f64::MIN_POSITIVE) }
+    }
+
+    fn x(&self, value: f64) -> f64 {
+        Self::LEFT + value / self.x_max * (self.width - Self::LEFT - Self::RIGHT)
+    }
+
+    fn y(&self, value: f64) -> f64 {
+        self.height - Self::BOTTOM - value / self.y_max * (self.height - Self::TOP - Self::BOTTOM)
+    }
+
+    /// Opens an SVG element and draws its title, axes and tick labels
+    fn open(&self, html: &mut String, title: &str, x_label: &str, y_label: &str) {
+        let (x0, y0) = (self.x(0.0), self.y(0.0));
+        let (x1, y1) = (self.x(self.x_max), self.y(self.y_max));
+        let _ = write!(
+            html,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\
+<text x=\"{cx:.1}\" y=\"14\" text-anchor=\"middle\" font-weight=\"bold\">{title}</text>\
+<line x1=\"{x0:.1}\" y1=\"{y0:.1}\" x2=\"{x1:.1}\" y2=\"{y0:.1}\" stroke=\"#333\"/>\
+<line x1=\"{x0:.1}\" y1=\"{y0:.1}\" x2=\"{x0:.1}\" y2=\"{y1:.1}\" stroke=\"#333\"/>\
+<text x=\"{cx:.1}\" y=\"{xl:.1}\" text-anchor=\"middle\">{x_label}</text>\
+<text transform=\"translate(12,{cy:.1}) rotate(-90)\" text-anchor=\"middle\">{y_label}</text>",
+            w = self.width, h = self.height,
+            cx = (x0 + x1) / 2.0, cy = (y0 + y1) / 2.0, xl = self.height - 4.0,
+            title = escape(title), x_label = escape(x_label), y_label = escape(y_label),
+            x0 = x0, y0 = y0, x1 = x1, y1 = y1
+        );
+        for frac in [0.0, 0.5, 1.0] {
+            let _ = write!(
+                html,
+                "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>\
+<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>",
+                self.x(frac * self.x_max), y0 + 12.0, tick(frac * self.x_max),
+                x0 - 4.0, self.y(frac * self.y_max) + 3.0, tick(frac * self.y_max)
+            );
+        }
+    }
+}
+
+/// Formats an axis tick label
+fn tick(value: f64) -> String {
+    if value >= 100.0 || value == value.round() {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+fn color(sample: usize) -> &'static str {
+    PALETTE[sample % PALETTE.len()]
+}
+
+/// Escapes text for inclusion in HTML
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[test]
+fn test_report() {
+    let table = CountTable {
+        guides: (0..3).map(|x| x.to_string()).collect(),
+        genes: (0..3).map(|x| x.to_string()).collect(),
+        controls: vec![false; 3],
+        samples: vec!["a<1>".into(), "b".into()],
+        counts: vec![10.0, 12.0, 0.0, 3.0, 40.0, 35.0]
+    };
+    let mapping = [SampleStats { num_total: 100, num_assigned: 50, ..SampleStats::default() }; 2];
+    let mut html = Vec::new();
+    QcReport::new(&table)
+        .with_mapping(&mapping)
+        .write(&mut html)
+        .unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("a&lt;1&gt;"));
+    assert!(html.contains("50.00%"));
+    assert!(!html.contains("<script"));
+    assert_eq!(html.matches("<svg").count(), 2 + 1 + 1);
+
+    // no pairs are drawn across replicate groups
+    let report = QcReport::new(&table).with_groups(&["x", "y"]);
+    assert!(report.pairs.is_empty());
+}
@@ -0,0 +1,209 @@
+use std::{collections::HashMap, io::{Error, Write}};
+use super::{CountTable, GuideComparison};
+use super::stats::{Rng, benjamini_hochberg, binomial_sf, rank};
+
+/// Default fraction of top-ranked guides considered by alpha-RRA
+pub const DEFAULT_ALPHA: f64 = 0.25;
+
+/// Robust rank aggregation result of a gene in one direction of selection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RraScore {
+    pub score: f64,
+    pub pvalue: f64,
+    pub fdr: f64,
+    /// 1-based rank of the gene by score
+    pub rank: usize
+}
+
+/// Gene-level robust rank aggregation for negative and positive selection
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneRra {
+    pub gene: String,
+    pub n_guides: usize,
+    /// Mean log2 fold change of the gene's guides
+    pub log2fc: f64,
+    pub negative: RraScore,
+    pub positive: RraScore
+}
+
+/// Alpha-RRA score of a set of normalized guide ranks (percentiles in (0, 1])
+///
+/// Only percentiles below `alpha` contribute; the score is the smallest
+/// probability of the k-th smallest of `n` uniform percentiles being at
+/// most the observed one.
+pub fn alpha_rra(percentiles: &mut [f64], alpha: f64) -> f64 {
+    let n = percentiles.len();
+    percentiles.sort_by(f64::total_cmp);
+    percentiles
+        .iter()
+        .take_while(|u| **u <= alpha)
+        .enumerate()
+        .map(|(k, u)| binomial_sf(k + 1, n, *u))
+        .fold(1.0, f64::min)
+}
+
+/// Scores genes in one direction from the percentiles of their guides
+fn score_direction(groups: &[Vec<f64>], n_ranked: usize, alpha: f64, permutations: usize, rng: &mut Rng) -> Vec<RraScore> {
+    let scores: Vec<f64> = groups
+        .iter()
+        .map(|p| alpha_rra(&mut p.clone(), alpha))
+        .collect();
+
+    // null scores of random guide sets of each gene size
+    let mut null_scores: HashMap<usize, Vec<f64>> = HashMap::new();
+    let mut size_counts: HashMap<usize, usize> = HashMap::new();
+    for group in groups {
+        *size_counts.entry(group.len()).or_insert(0) += 1;
+    }
+    for (size, count) in size_counts {
+        let mut null: Vec<f64> = (0..permutations * count)
+            .map(|_| {
+                let mut percentiles: Vec<f64> = (0..size)
+                    .map(|_| (rng.below(n_ranked) + 1) as f64 / n_ranked as f64)
+                    .collect();
+                alpha_rra(&mut percentiles, alpha)
+            })
+            .collect();
+        null.sort_by(f64::total_cmp);
+        null_scores.insert(size, null);
+    }
+
+    let pvalues: Vec<f64> = groups
+        .iter()
+        .zip(scores.iter())
+        .map(|(group, score)| {
+            let null = &null_scores[&group.len()];
+            let n_extreme = null.partition_point(|x| x <= score);
+            (n_extreme + 1) as f64 / (null.len() + 1) as f64
+        })
+        .collect();
+    let fdrs = benjamini_hochberg(&pvalues);
+
+    let mut order: Vec<usize> = (0..groups.len()).collect();
+    order.sort_by(|a, b| scores[*a].total_cmp(&scores[*b]));
+    let mut ranks = vec![0; groups.len()];
+    for (pos, idx) in order.iter().enumerate() {
+        ranks[*idx] = pos + 1;
+    }
+
+    (0..groups.len())
+        .map(|g| RraScore { score: scores[g], pvalue: pvalues[g], fdr: fdrs[g], rank: ranks[g] })
+        .collect()
+}
+
+/// Ranks guides by log2 fold change and aggregates them into gene scores
+///
+/// Guides are ranked against all guides with a finite fold change, control
+/// guides included, while only non-control genes are scored. Tied fold
+/// changes share their average rank. P-values come
+/// from `permutations` random guide sets per gene and are FDR-adjusted with
+/// Benjamini-Hochberg.
+pub fn rank_genes(table: &CountTable, comparisons: &[GuideComparison], alpha: f64, permutations: usize, rng: &mut Rng) -> Vec<GeneRra> {
+    let ranked: Vec<usize> = (0..comparisons.len())
+        .filter(|g| comparisons[*g].log2fc.is_finite())
+        .collect();
+    let n_ranked = ranked.len();
+    let ranks = rank(&ranked.iter().map(|g| comparisons[*g].log2fc).collect::<Vec<_>>());
+    let mut percentile = vec![f64::NAN; comparisons.len()];
+    for (guide, r) in ranked.iter().zip(ranks) {
+        percentile[*guide] = r / n_ranked as f64;
+    }
+
+    let mut genes: Vec<&str> = Vec::new();
+    let mut members: HashMap<&str, Vec<usize>> = HashMap::new();
+    for guide in ranked {
+        if table.controls[guide] {
+            continue;
+        }
+        let gene = table.genes[guide].as_str();
+        members.entry(gene)
+            .or_insert_with(|| {
+                genes.push(gene);
+                Vec::new()
+            })
+            .push(guide);
+    }
+
+    let negative: Vec<Vec<f64>> = genes
+        .iter()
+        .map(|g| members[g].iter().map(|x| percentile[*x]).collect())
+        .collect();
+    let positive: Vec<Vec<f64>> = genes
+        .iter()
+        .map(|g| members[g].iter().map(|x| 1.0 - percentile[*x] + 1.0 / n_ranked as f64).collect())
+        .collect();
+    let negative = score_direction(&negative, n_ranked, alpha, permutations, rng);
+    let positive = score_direction(&positive, n_ranked, alpha, permutations, rng);
+
+    genes.iter()
+        .enumerate()
+        .map(|(idx, gene)| {
+            let guides = &members[gene];
+            GeneRra {
+                gene: gene.to_string(),
+                n_guides: guides.len(),
+                log2fc: guides.iter().map(|g| comparisons[*g].log2fc).sum::<f64>() / guides.len() as f64,
+                negative: negative[idx],
+                positive: positive[idx]
+            }
+        })
+        .collect()
+}
+
+/// Writes gene RRA results as a TSV
+pub fn write_rra<W: Write>(writer: &mut W, genes: &[GeneRra]) -> Result<(), Error> {
+    writeln!(writer, "Gene\tGuides\tLog2FC\tneg_score\tneg_pvalue\tneg_fdr\tneg_rank\tpos_score\tpos_pvalue\tpos_fdr\tpos_rank")?;
+    for g in genes {
+        writeln!(
+            writer,
+            "{}\t{}\t{:.6}\t{:.6e}\t{:.6e}\t{:.6}\t{}\t{:.6e}\t{:.6e}\t{:.6}\t{}",
+            g.gene, g.n_guides, g.log2fc,
+            g.negative.score, g.negative.pvalue, g.negative.fdr, g.negative.rank,
+            g.positive.score, g.positive.pvalue, g.positive.fdr, g.positive.rank
+        )?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_alpha_rra() {
+    // a single guide at the top percentile of 4 guides
+    assert!((alpha_rra(&mut [0.01, 0.9, 0.8, 0.7], 0.25) - (1.0 - 0.99f64.powi(4))).abs() < 1e-12);
+    assert_eq!(alpha_rra(&mut [0.5, 0.9], 0.25), 1.0);
+}
+
+#[test]
+fn test_rank_genes() {
+    let mut table = CountTable::default();
+    let mut comparisons = Vec::new();
+    let mut push = |gene: &str, lfc: f64, control: bool| {
+        table.guides.push(format!("{}_{}", gene, table.guides.len()));
+        table.genes.push(gene.to_string());
+        table.controls.push(control);
+        comparisons.push(GuideComparison { reference: 0.0, treatment: 0.0, log2fc: lfc });
+    };
+    for idx in 0..400 {
+        push(&format!("G{}", idx / 4), ((idx * 37) % 400) as f64 / 100.0 - 2.0, false);
+    }
+    for lfc in [-5.0, -4.5, -4.8, -4.9] {
+        push("DOWN", lfc, false);
+    }
+    for lfc in [5.0, 4.5, 4.8, 4.9] {
+        push("UP", lfc, false);
+    }
+    let genes = rank_genes(&table, &comparisons, DEFAULT_ALPHA, 100, &mut Rng::new(1));
+    let down = genes.iter().find(|g| g.gene == "DOWN").unwrap();
+    let up = genes.iter().find(|g| g.gene == "UP").unwrap();
+    assert_eq!(down.negative.rank, 1);
+    assert_eq!(up.positive.rank, 1);
+    assert!(down.negative.fdr < 0.05);
+    assert!(up.positive.fdr < 0.05);
+    assert!(down.positive.pvalue > 0.5);
+
+    // a sample compared with itself has every guide tied at a fold change of 0
+    for comparison in comparisons.iter_mut() {
+        comparison.log2fc = 0.0;
+    }
+    let genes = rank_genes(&table, &comparisons, DEFAULT_ALPHA, 100, &mut Rng::new(1));
+    assert!(genes.iter().all(|g| g.negative.fdr == 1.0 && g.positive.fdr == 1.0));
+}
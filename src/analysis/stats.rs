@@ -0,0 +1,425 @@
+use std::collections::HashMap;
+
+/// Complementary error function
+///
+/// Chebyshev approximation with a fractional error below 1.2e-7 everywhere
+/// (Numerical Recipes `erfcc`).
+pub fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t * (-z * z - 1.265_512_23 + t * (1.000_023_68 + t * (0.374_091_96 + t * (0.096_784_18
+        + t * (-0.186_288_06 + t * (0.278_868_07 + t * (-1.135_203_98 + t * (1.488_515_87
+        + t * (-0.822_152_23 + t * 0.170_872_77))))))))).exp();
+    if x >= 0.0 { r } else { 2.0 - r }
+}
+
+/// Upper tail probability of the standard normal distribution
+pub fn normal_sf(z: f64) -> f64 {
+    0.5 * erfc(z / std::f64::consts::SQRT_2)
+}
+
+/// Ranks of the values (1-based), averaging the ranks of ties
+pub fn rank(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|a, b| values[*a].partial_cmp(&values[*b]).unwrap());
+    let mut ranks = vec![0.0; values.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && values[order[end]] == values[order[start]] {
+            end += 1;
+        }
+        let avg = (start + end + 1) as f64 / 2.0;
+        for idx in order[start..end].iter() {
+            ranks[*idx] = avg;
+        }
+        start = end;
+    }
+    ranks
+}
+
+/// Largest group tested exactly when ties dominate a Mann-Whitney test
+const EXACT_MAX_SIZE: usize = 10;
+
+/// Largest number of distinct values tested exactly when ties dominate a
+/// Mann-Whitney test
+const EXACT_MAX_BLOCKS: usize = 256;
+
+/// Two-sided Mann-Whitney U test of `x` against `y`
+///
+/// Returns the U statistic of `x` and its p-value from the tie-corrected
+/// normal approximation with continuity correction. When ties remove most of
+/// the variance (e.g. nearly every score is 0) the normal approximation badly
+/// overstates significance, so small groups are tested exactly against the
+/// permutation distribution of the tied ranks instead.
+pub fn mann_whitney_u(x: &[f64], y: &[f64]) -> (f64, f64) {
+    let (n1, n2) = (x.len() as f64, y.len() as f64);
+    if x.is_empty() || y.is_empty() {
+        return (0.0, 1.0);
+    }
+    let combined: Vec<f64> = x.iter().chain(y.iter()).copied().collect();
+    let ranks = rank(&combined);
+    let r1: f64 = ranks[..x.len()].iter().sum();
+    let u = r1 - n1 * (n1 + 1.0) / 2.0;
+
+    // tie correction of the variance, keeping the size and doubled rank of
+    // each block of tied values
+    let mut sorted = combined;
+    sorted.sort_by(f64::total_cmp);
+    let mut tie_sum = 0.0;
+    let mut blocks = Vec::new();
+    let mut start = 0;
+    while start < sorted.len() {
+        let end = start + sorted[start..].iter().take_while(|v| **v == sorted[start]).count();
+        let t = (end - start) as f64;
+        tie_sum += t * t * t - t;
+        blocks.push((end - start, start + end + 1));
+        start = end;
+    }
+    let n = n1 + n2;
+    let tie_correction = tie_sum / (n * (n - 1.0));
+    if tie_correction > (n + 1.0) / 2.0 && blocks.len() <= EXACT_MAX_BLOCKS {
+        if x.len() <= EXACT_MAX_SIZE {
+            return (u, exact_rank_sum_pvalue(&blocks, x.len(), (2.0 * r1).round() as usize));
+        }
+        if y.len() <= EXACT_MAX_SIZE {
+            let r2 = n * (n + 1.0) / 2.0 - r1;
+            return (u, exact_rank_sum_pvalue(&blocks, y.len(), (2.0 * r2).round() as usize));
+        }
+    }
+    let variance = n1 * n2 / 12.0 * ((n + 1.0) - tie_correction);
+    if variance <= 0.0 {
+        return (u, 1.0);
+    }
+    let mean = n1 * n2 / 2.0;
+    let z = ((u - mean).abs() - 0.5).max(0.0) / variance.sqrt();
+    (u, (2.0 * normal_sf(z)).min(1.0))
+}
+
+/// Two-sided p-value of a rank sum over all equally likely draws of `k`
+/// values from blocks of tied values
+///
+/// Blocks are given as their size and doubled (average) rank, and the
+/// observed rank sum is doubled as well so that sums stay integral.
+fn exact_rank_sum_pvalue(blocks: &[(usize, usize)], k: usize, observed: usize) -> f64 {
+    let n: usize = blocks.iter().map(|(t, _)| t).sum();
+
+    // number of draws of each size reaching each doubled rank sum
+    let mut ways: Vec<HashMap<usize, f64>> = vec![HashMap::new(); k + 1];
+    ways[0].insert(0, 1.0);
+    for (size, rank) in blocks {
+        for drawn in (0..k).rev() {
+            let current: Vec<(usize, f64)> = ways[drawn].iter().map(|(s, w)| (*s, *w)).collect();
+            for c in 1..=(*size).min(k - drawn) {
+                let choices = choose(*size, c);
+                for (sum, w) in current.iter() {
+                    *ways[drawn + c].entry(sum + c * rank).or_insert(0.0) += w * choices;
+                }
+            }
+        }
+    }
+
+    let center = (k * (n + 1)) as f64;
+    let distance = (observed as f64 - center).abs();
+    let extreme: f64 = ways[k].iter()
+        .filter(|(sum, _)| (**sum as f64 - center).abs() >= distance)
+        .map(|(_, w)| w)
+        .sum();
+    (extreme / choose(n, k)).min(1.0)
+}
+
+/// Binomial coefficient
+fn choose(n: usize, k: usize) -> f64 {
+    (0..k).fold(1.0, |acc, i| acc * (n - i) as f64 / (i + 1) as f64)
+}
+
+/// Sample standard deviation
+pub fn std_dev(values: &[f64]) -> f64 {
+    if values.len() < 2 {
+        return 0.0;
+    }
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let ss: f64 = values.iter().map(|v| (v - mean).powi(2)).sum();
+    (ss / (values.len() - 1) as f64).sqrt()
+}
+
+/// Small seedable pseudo-random generator (SplitMix64) so that
+/// permutations and pseudogenes are reproducible across runs
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64
+}
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform integer in `0..n`
+    pub fn below(&mut self, n: usize) -> usize {
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+
+    /// Draws `k` distinct items of a slice (partial Fisher-Yates shuffle)
+    pub fn sample<T: Copy>(&mut self, items: &[T], k: usize) -> Vec<T> {
+        let mut pool = items.to_vec();
+        let k = k.min(pool.len());
+        for i in 0..k {
+            let j = i + self.below(pool.len() - i);
+            pool.swap(i, j);
+        }
+        pool.truncate(k);
+        pool
+    }
+}
+
+#[test]
+fn test_normal_sf() {
+    assert!((normal_sf(0.0) - 0.5).abs() < 1e-7);
+    assert!((normal_sf(1.959964) - 0.025).abs() < 1e-7);
+    assert!((normal_sf(-1.959964) - 0.975).abs() < 1e-7);
+}
+
+#[test]
+fn test_mann_whitney_u() {
+    assert_eq!(rank(&[3.0, 1.0, 3.0, 2.0]), vec![3.5, 1.0, 3.5, 2.0]);
+
+    // matches scipy.stats.mannwhitneyu(x, y, method="asymptotic")
+    let x = [1.0, 2.0, 3.0, 4.0, 5.0];
+    let y = [6.0, 7.0, 8.0, 9.0, 10.0];
+    let (u, p) = mann_whitney_u(&x, &y);
+    assert_eq!(u, 0.0);
+    assert!((p - 0.0121858).abs() < 1e-6);
+    assert_eq!(mann_whitney_u(&x, &x).1, 1.0);
+}
+
+#[test]
+fn test_mann_whitney_u_ties() {
+    // a single non-zero guide among controls almost all tied at 0 is only
+    // drawn into the group by chance with probability ~2 * 3 / 1004
+    let x = [0.1, 0.0, 0.0];
+    let mut y = vec![0.0; 1000];
+    y.push(-0.1);
+    let (_, p) = mann_whitney_u(&x, &y);
+    assert!(p > 1e-3 && p < 0.05, "p = {}", p);
+    assert_eq!(mann_whitney_u(&y, &x).1, p);
+    assert_eq!(mann_whitney_u(&[0.0; 3], &y).1, 1.0);
+
+    // untied ranks 1..4 drawn in pairs: only {1, 2} and {3, 4} are as extreme as {1, 2}
+    assert!((exact_rank_sum_pvalue(&[(1, 2), (1, 4), (1, 6), (1, 8)], 2, 6) - 2.0 / 6.0).abs() < 1e-12);
+}
+
+#[test]
+fn test_rng() {
+    let mut a = Rng::new(7);
+    let mut b = Rng::new(7);
+    assert_eq!(a.next_u64(), b.next_u64());
+    let sample = a.sample(&[1, 2, 3, 4, 5], 3);
+    assert_eq!(sample.len(), 3);
+    assert!(a.below(10) < 10);
+}
+
+/// Probability that a binomial(n, p) variable is at least `k`
+pub fn binomial_sf(k: usize, n: usize, p: f64) -> f64 {
+    if k == 0 {
+        return 1.0;
+    }
+    if k > n {
+        return 0.0;
+    }
+    let log_p = p.ln();
+    let log_q = (1.0 - p).ln();
+    let mut log_choose = 0.0;
+    let mut total = 0.0;
+    for i in 0..=n {
+        if i > 0 {
+            log_choose += ((n - i + 1) as f64).ln() - (i as f64).ln();
+        }
+        if i >= k {
+            let term = log_choose + i as f64 * log_p + (n - i) as f64 * log_q;
+            total += term.exp();
+        }
+    }
+    total.min(1.0)
+}
+
+/// Benjamini-Hochberg adjusted p-values
+pub fn benjamini_hochberg(pvalues: &[f64]) -> Vec<f64> {
+    let n = pvalues.len();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|a, b| pvalues[*b].partial_cmp(&pvalues[*a]).unwrap());
+    let mut adjusted = vec![1.0; n];
+    let mut running = 1.0_f64;
+    for (pos, idx) in order.iter().enumerate() {
+        let rank = (n - pos) as f64;
+        running = running.min(pvalues[*idx] * n as f64 / rank);
+        adjusted[*idx] = running.min(1.0);
+    }
+    adjusted
+}
+
+#[test]
+fn test_binomial_sf() {
+    assert_eq!(binomial_sf(0, 5, 0.3), 1.0);
+    assert_eq!(binomial_sf(6, 5, 0.3), 0.0);
+    assert!((binomial_sf(1, 3, 0.5) - 0.875).abs() < 1e-12);
+    assert!((binomial_sf(3, 3, 0.5) - 0.125).abs() < 1e-12);
+}
+
+#[test]
+fn test_benjamini_hochberg() {
+    let adjusted = benjamini_hochberg(&[0.01, 0.04, 0.03, 0.5]);
+    let expected = [0.04, 0.0533333, 0.0533333, 0.5];
+    assert!(adjusted.iter().zip(expected.iter()).all(|(x, y)| (x - y).abs() < 1e-6));
+}
+
+/// Natural log of the gamma function (Lanczos approximation)
+pub fn ln_gamma(x: f64) -> f64 {
+    const COEF: [f64; 6] = [
+        76.180_091_729_471_46, -86.505_320_329_416_77, 24.014_098_240_830_91,
+        -1.231_739_572_450_155, 0.120_865_097_386_617_9e-2, -0.539_523_938_495_3e-5
+    ];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut ser = 1.000_000_000_190_015;
+    for (j, c) in COEF.iter().enumerate() {
+        ser += c / (x + 1.0 + j as f64);
+    }
+    -tmp + (2.506_628_274_631_000_5 * ser / x).ln()
+}
+
+const MAX_ITER: usize = 10_000;
+const EPS: f64 = 1e-14;
+const FPMIN: f64 = 1e-300;
+
+/// Continued fraction of the incomplete beta function (modified Lentz)
+fn beta_cf(a: f64, b: f64, x: f64) -> f64 {
+    let (qab, qap, qam) = (a + b, a + 1.0, a - 1.0);
+    let mut c = 1.0;
+    let mut d = 1.0 - qab * x / qap;
+    if d.abs() < FPMIN { d = FPMIN; }
+    d = 1.0 / d;
+    let mut h = d;
+    for m in 1..MAX_ITER {
+        let m = m as f64;
+        let m2 = 2.0 * m;
+        let aa = m * (b - m) * x / ((qam + m2) * (a + m2));
+        d = 1.0 + aa * d;
+        if d.abs() < FPMIN { d = FPMIN; }
+        c = 1.0 + aa / c;
+        if c.abs() < FPMIN { c = FPMIN; }
+        d = 1.0 / d;
+        h *= d * c;
+        let aa = -(a + m) * (qab + m) * x / ((a + m2) * (qap + m2));
+        d = 1.0 + aa * d;
+        if d.abs() < FPMIN { d = FPMIN; }
+        c = 1.0 + aa / c;
+        if c.abs() < FPMIN { c = FPMIN; }
+        d = 1.0 / d;
+        let del = d * c;
+        h *= del;
+        if (del - 1.0).abs() < EPS {
+            break;
+        }
+    }
+    h
+}
+
+/// Regularized incomplete beta function I_x(a, b)
+pub fn regularized_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_cf(a, b, x) / a
+    } else {
+        1.0 - front * beta_cf(b, a, 1.0 - x) / b
+    }
+}
+
+/// Regularized upper incomplete gamma function Q(a, x)
+pub fn regularized_gamma_q(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let log_front = -x + a * x.ln() - ln_gamma(a);
+    if x < a + 1.0 {
+        // series representation of P(a, x)
+        let mut ap = a;
+        let mut del = 1.0 / a;
+        let mut sum = del;
+        for _ in 0..MAX_ITER {
+            ap += 1.0;
+            del *= x / ap;
+            sum += del;
+            if del.abs() < sum.abs() * EPS {
+                break;
+            }
+        }
+        1.0 - sum * log_front.exp()
+    } else {
+        // continued fraction representation of Q(a, x)
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / FPMIN;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..MAX_ITER {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < FPMIN { d = FPMIN; }
+            c = b + an / c;
+            if c.abs() < FPMIN { c = FPMIN; }
+            d = 1.0 / d;
+            let del = d * c;
+            h *= del;
+            if (del - 1.0).abs() < EPS {
+                break;
+            }
+        }
+        log_front.exp() * h
+    }
+}
+
+/// P(X <= k) of a negative binomial with the given mean and variance
+///
+/// Variances not exceeding the mean fall back to a Poisson distribution.
+pub fn nb_cdf(k: f64, mean: f64, variance: f64) -> f64 {
+    if k < 0.0 {
+        return 0.0;
+    }
+    let k = k.floor();
+    if mean <= 0.0 {
+        return 1.0;
+    }
+    if variance <= mean * (1.0 + 1e-8) {
+        return regularized_gamma_q(k + 1.0, mean);
+    }
+    let p = mean / variance;
+    let r = mean * mean / (variance - mean);
+    regularized_beta(r, k + 1.0, p)
+}
+
+#[test]
+fn test_special_functions() {
+    assert!((ln_gamma(5.0) - 24f64.ln()).abs() < 1e-10);
+    assert!((ln_gamma(0.5) - std::f64::consts::PI.sqrt().ln()).abs() < 1e-10);
+    assert!((regularized_beta(2.0, 3.0, 0.4) - 0.5248).abs() < 1e-10);
+    assert!((regularized_gamma_q(3.0, 2.0) - 0.676_676_416_183_063_9).abs() < 1e-10);
+
+    // Poisson(2): P(X <= 2) = 5 e^-2
+    assert!((nb_cdf(2.0, 2.0, 2.0) - 5.0 * (-2f64).exp()).abs() < 1e-10);
+    // NB with r = 2, p = 0.5 (mean 2, variance 4): P(X <= 1) = 0.25 + 0.25
+    assert!((nb_cdf(1.0, 2.0, 4.0) - 0.5).abs() < 1e-10);
+}
@@ -0,0 +1,173 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// Number of GC content bins (10% wide) reported for a library
+pub const GC_BINS: usize = 10;
+
+/// Statistics accumulated while guides are loaded into a library
+#[derive(Debug, Default, Clone)]
+pub struct LoadStats {
+    pub num_records: usize,
+    pub num_duplicate_names: usize,
+    pub num_duplicate_seqs: usize,
+    pub num_missing_anchor: usize,
+    pub num_unpackable: usize,
+    pub num_cross_collisions: usize,
+    pub num_filtered: usize,
+    pub spacer_lengths: BTreeMap<usize, usize>,
+    pub gc_bins: [usize; GC_BINS]
+}
+impl LoadStats {
+
+    /// Adds the statistics of another load
+    pub fn merge(&mut self, other: &Self) {
+        self.num_records += other.num_records;
+        self.num_duplicate_names += other.num_duplicate_names;
+        self.num_duplicate_seqs += other.num_duplicate_seqs;
+        self.num_missing_anchor += other.num_missing_anchor;
+        self.num_unpackable += other.num_unpackable;
+        self.num_cross_collisions += other.num_cross_collisions;
+        self.num_filtered += other.num_filtered;
+        for (len, n) in other.spacer_lengths.iter() {
+            *self.spacer_lengths.entry(*len).or_insert(0) += n;
+        }
+        for (bin, n) in self.gc_bins.iter_mut().zip(other.gc_bins.iter()) {
+            *bin += n;
+        }
+    }
+
+    /// Records the length and GC content of a protospacer
+    pub fn add_spacer(&mut self, spacer: &[u8]) {
+        *self.spacer_lengths.entry(spacer.len()).or_insert(0) += 1;
+        if !spacer.is_empty() {
+            let gc = spacer.iter()
+                .filter(|b| matches!(b, b'G' | b'C' | b'g' | b'c'))
+                .count();
+            let bin = (gc * GC_BINS / spacer.len()).min(GC_BINS - 1);
+            self.gc_bins[bin] += 1;
+        }
+    }
+}
+
+/// Guide pairs separated by few mismatches
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloseGuides {
+    /// Minimum pairwise Hamming distance (none when no two guides share a length)
+    pub min_distance: Option<usize>,
+    /// Number of guide pairs at each distance up to the searched range
+    /// (index = mismatches, duplicated sequences are counted at 0)
+    pub pairs: Vec<usize>
+}
+
+/// Sequence length encoded by a packed key
+fn key_len(key: u64) -> usize {
+    (63 - key.leading_zeros() as usize) / 2
+}
+
+/// Number of mismatched bases between two packed keys of equal length
+pub fn hamming_distance(a: u64, b: u64) -> usize {
+    let x = a ^ b;
+    ((x | (x >> 1)) & 0x5555_5555_5555_5555).count_ones() as usize
+}
+
+/// Bit mask covering bases `start..end` of a packed key of length `len`
+fn segment_mask(len: usize, start: usize, end: usize) -> u64 {
+    let width = 2 * (end - start);
+    let shift = 2 * (len - end);
+    if width == 64 {
+        u64::MAX
+    } else {
+        ((1u64 << width) - 1) << shift
+    }
+}
+
+/// Finds guide pairs within `max_mismatches` of each other along with the
+/// minimum distance between any two guides
+///
+/// Keys are compared only against keys of the same length, and only
+/// the first `len - anchor_len` bases (the protospacer) are used to
+/// bucket candidates: by the pigeonhole principle two sequences within
+/// `k` mismatches share at least one of `k + 1` segments exactly, so
+/// each pair only needs comparing within those buckets. When no pair is
+/// within `max_mismatches` the search is widened until the closest pair
+/// is found.
+pub fn find_close_guides<I: IntoIterator<Item = u64>>(keys: I, anchor_len: usize, max_mismatches: usize) -> CloseGuides {
+    let mut by_length: HashMap<usize, Vec<u64>> = HashMap::new();
+    for key in keys {
+        by_length.entry(key_len(key)).or_default().push(key);
+    }
+    by_length.retain(|_, keys| keys.len() > 1);
+
+    let pairs = count_close_pairs(&by_length, anchor_len, max_mismatches);
+    let mut min_distance = pairs.iter().position(|n| *n > 0);
+    let max_len = by_length.keys().copied().max().unwrap_or(0);
+    let mut k = max_mismatches;
+    while min_distance.is_none() && k < max_len {
+        k = (2 * k + 1).min(max_len);
+        min_distance = count_close_pairs(&by_length, anchor_len, k).iter().position(|n| *n > 0);
+    }
+    CloseGuides { min_distance, pairs }
+}
+
+/// Number of guide pairs at each distance up to `max_mismatches`
+fn count_close_pairs(by_length: &HashMap<usize, Vec<u64>>, anchor_len: usize, max_mismatches: usize) -> Vec<usize> {
+    let mut pairs = vec![0; max_mismatches + 1];
+    for (len, keys) in by_length.iter() {
+        let len = *len;
+        let variable = if len > anchor_len { len - anchor_len } else { len };
+        // pairs may differ at every base once `max_mismatches` covers the
+        // protospacer, leaving a single bucket of all keys
+        let masks: Vec<u64> = if max_mismatches >= variable {
+            vec![0]
+        } else {
+            let n_segments = max_mismatches + 1;
+            (0..n_segments)
+                .map(|s| segment_mask(len, s * variable / n_segments, (s + 1) * variable / n_segments))
+                .collect()
+        };
+
+        for (s, mask) in masks.iter().enumerate() {
+            let mut buckets: HashMap<u64, Vec<u64>> = HashMap::new();
+            for key in keys.iter() {
+                buckets.entry(key & mask).or_default().push(*key);
+            }
+            for bucket in buckets.values() {
+                for (i, a) in bucket.iter().enumerate() {
+                    for b in bucket[i + 1..].iter() {
+                        // pairs sharing an earlier segment were already counted
+                        if masks[..s].iter().any(|m| (a ^ b) & m == 0) {
+                            continue;
+                        }
+                        let dist = hamming_distance(*a, *b);
+                        if dist <= max_mismatches {
+                            pairs[dist] += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    pairs
+}
+
+#[test]
+fn test_find_close_guides() {
+    use super::encode_seq;
+    let seqs: [&[u8]; 4] = [
+        b"AAAAAAAAAAGTTTAAGAG",
+        b"AAAAAAAAATGTTTAAGAG",
+        b"AAAATAAAATGTTTAAGAG",
+        b"CCCCCCCCCCGTTTAAGAG"
+    ];
+    let keys: Vec<u64> = seqs.iter().map(|s| encode_seq(s).unwrap()).collect();
+    assert_eq!(hamming_distance(keys[0], keys[2]), 2);
+
+    let close = find_close_guides(keys.clone(), 9, 2);
+    assert_eq!(close.min_distance, Some(1));
+    assert_eq!(close.pairs, vec![0, 2, 1]);
+
+    // the minimum distance is reported beyond the counted range
+    let close = find_close_guides(vec![keys[0], keys[3]], 9, 2);
+    assert_eq!(close.min_distance, Some(10));
+    assert_eq!(close.pairs, vec![0, 0, 0]);
+    assert_eq!(find_close_guides(vec![keys[0]], 9, 2).min_distance, None);
+}
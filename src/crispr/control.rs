@@ -0,0 +1,58 @@
+use std::{collections::HashSet, io::{Error, ErrorKind}};
+use regex::Regex;
+use super::filter::read_name_list;
+
+/// Default gene pattern identifying non-targeting / negative control guides
+pub const DEFAULT_CONTROL_PATTERN: &str = "(?i)^(non-targeting|negative[_-]?control)";
+
+/// Designates which guides of a library are controls
+///
+/// A guide is a control if its gene matches the pattern or if either its
+/// name or gene is one of the listed identifiers.
+#[derive(Debug, Clone)]
+pub struct ControlSpec {
+    pattern: Option<Regex>,
+    names: HashSet<String>
+}
+impl Default for ControlSpec {
+    fn default() -> Self {
+        Self::from_pattern(DEFAULT_CONTROL_PATTERN)
+            .expect("default control pattern is valid")
+    }
+}
+impl ControlSpec {
+
+    /// Controls are guides whose gene matches the provided pattern
+    pub fn from_pattern(pattern: &str) -> Result<Self, Error> {
+        let pattern = Regex::new(pattern)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+        Ok(Self { pattern: Some(pattern), names: HashSet::new() })
+    }
+
+    /// Controls are the guides or genes listed one per line in a file
+    pub fn from_list(filename: &str) -> Result<Self, Error> {
+        Ok(Self { pattern: None, names: read_name_list(filename)? })
+    }
+
+    /// Additionally treats guides matching the pattern of another spec as controls
+    pub fn with_pattern_of(mut self, other: Self) -> Self {
+        self.pattern = other.pattern;
+        self
+    }
+
+    /// Whether the guide is a control guide
+    pub fn is_control(&self, name: &str, gene: &str) -> bool {
+        self.names.contains(name)
+            || self.names.contains(gene)
+            || self.pattern.as_ref().is_some_and(|p| p.is_match(gene))
+    }
+}
+
+#[test]
+fn test_control_spec() {
+    let spec = ControlSpec::default();
+    assert!(spec.is_control("non-targeting_00000_h1_Top5", "non-targeting"));
+    assert!(spec.is_control("NegCtrl_1", "negative_control"));
+    assert!(!spec.is_control("AADACL2_+_151451732.23-P1P2_h1_Top5", "AADACL2"));
+    assert!(ControlSpec::from_pattern("(").is_err());
+}
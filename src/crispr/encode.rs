@@ -0,0 +1,138 @@
+use std::hash::{BuildHasherDefault, Hasher};
+
+/// Maximum sequence length which can be packed into a `u64`
+pub const MAX_ENCODED_LEN: usize = 31;
+
+/// Marks bytes which are not nucleotides in `BASE_CODES`
+const INVALID_BASE: u8 = 0b100;
+
+/// Builds the byte -> 2-bit code lookup table
+const fn build_base_codes() -> [u8; 256] {
+    let mut table = [INVALID_BASE; 256];
+    table[b'A' as usize] = 0;
+    table[b'a' as usize] = 0;
+    table[b'C' as usize] = 1;
+    table[b'c' as usize] = 1;
+    table[b'G' as usize] = 2;
+    table[b'g' as usize] = 2;
+    table[b'T' as usize] = 3;
+    table[b't' as usize] = 3;
+    table
+}
+
+/// 2-bit code for each nucleotide byte
+static BASE_CODES: [u8; 256] = build_base_codes();
+
+/// Packs a nucleotide sequence into a `u64` using 2 bits per base
+///
+/// A leading sentinel bit marks the sequence length so that sequences
+/// of different lengths never collide (i.e. `A` != `AA`). Returns `None`
+/// for sequences containing non-ACGT bases or longer than `MAX_ENCODED_LEN`.
+pub fn encode_seq(seq: &[u8]) -> Option<u64> {
+    if seq.len() > MAX_ENCODED_LEN {
+        return None;
+    }
+
+    // accumulate invalid flags rather than branching on every base
+    let mut key = 1u64;
+    let mut flags = 0u8;
+    for base in seq {
+        let code = BASE_CODES[*base as usize];
+        flags |= code;
+        key = (key << 2) | (code & 0b11) as u64;
+    }
+
+    if flags & INVALID_BASE == 0 {
+        Some(key)
+    } else {
+        None
+    }
+}
+
+/// Library key of a guide sequence
+///
+/// Sequences up to `MAX_ENCODED_LEN` are packed into a `u64`, while longer
+/// ones fall back to their uppercase bases so that any length can be matched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GuideKey {
+    Packed(u64),
+    Bases(Vec<u8>)
+}
+impl GuideKey {
+
+    /// Builds the key of a sequence, or `None` if it has non-ACGT bases
+    pub fn new(seq: &[u8]) -> Option<Self> {
+        if seq.len() <= MAX_ENCODED_LEN {
+            encode_seq(seq).map(GuideKey::Packed)
+        } else if seq.iter().all(|b| BASE_CODES[*b as usize] != INVALID_BASE) {
+            Some(GuideKey::Bases(seq.to_ascii_uppercase()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Unpacks a `u64` created by `encode_seq` back into its sequence
+pub fn decode_seq(key: u64) -> String {
+    let len = (63 - key.leading_zeros() as usize) / 2;
+    (0..len)
+        .rev()
+        .map(|i| {
+            match (key >> (2 * i)) & 0b11 {
+                0 => 'A',
+                1 => 'C',
+                2 => 'G',
+                _ => 'T'
+            }})
+        .collect()
+}
+
+/// Lightweight hasher for packed sequence keys
+///
+/// Packed keys differ mostly in their low bits, so a single
+/// multiply-xorshift finalizer is enough to spread them over the table.
+#[derive(Default)]
+pub struct GuideHasher {
+    hash: u64
+}
+impl Hasher for GuideHasher {
+    fn finish(&self) -> u64 {
+        let x = self.hash.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        x ^ (x >> 32)
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.hash = self.hash.rotate_left(8) ^ (*b as u64);
+        }
+    }
+    fn write_u64(&mut self, i: u64) {
+        self.hash ^= i;
+    }
+}
+
+/// Hash builder for maps keyed on packed sequences
+pub type GuideHashBuilder = BuildHasherDefault<GuideHasher>;
+
+#[test]
+fn test_encode_roundtrip() {
+    let seq = "GTAGAGCAATTGTAGACTTGTTTAAGAG";
+    let key = encode_seq(seq.as_bytes()).expect("Unable to encode sequence");
+    assert_eq!(decode_seq(key), seq);
+    assert_eq!(encode_seq(seq.to_lowercase().as_bytes()), Some(key));
+}
+
+#[test]
+fn test_guide_key() {
+    let long = [b'a'; MAX_ENCODED_LEN + 1];
+    assert_eq!(GuideKey::new(b"ACGT"), encode_seq(b"ACGT").map(GuideKey::Packed));
+    assert_eq!(GuideKey::new(&long), Some(GuideKey::Bases(vec![b'A'; MAX_ENCODED_LEN + 1])));
+    assert_eq!(GuideKey::new(&[&long[..], b"N"].concat()), None);
+}
+
+#[test]
+fn test_encode_length_sentinel() {
+    assert_ne!(encode_seq(b"A"), encode_seq(b"AA"));
+    assert_eq!(decode_seq(encode_seq(b"").unwrap()), "");
+    assert!(encode_seq(b"ACGN").is_none());
+    assert!(encode_seq(&[b'A'; MAX_ENCODED_LEN + 1]).is_none());
+}
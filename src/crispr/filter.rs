@@ -0,0 +1,84 @@
+use std::{collections::HashSet, fs::File, io::{BufRead, BufReader, Error, ErrorKind}};
+use regex::Regex;
+
+/// Reads a list of identifiers, one per line, skipping blank and `#` lines
+pub fn read_name_list(filename: &str) -> Result<HashSet<String>, Error> {
+    let reader = BufReader::new(File::open(filename)?);
+    let mut names = HashSet::new();
+    for line in reader.lines() {
+        let line = line?;
+        let name = line.trim();
+        if !name.is_empty() && !name.starts_with('#') {
+            names.insert(name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// Restricts the guides loaded into a library to a subset
+///
+/// A guide is kept if its gene is in the include list (when given), is not in
+/// the exclude list and its name matches the header pattern (when given).
+#[derive(Debug, Clone, Default)]
+pub struct GuideFilter {
+    include_genes: Option<HashSet<String>>,
+    exclude_genes: HashSet<String>,
+    pattern: Option<Regex>,
+    off_panel: bool
+}
+impl GuideFilter {
+
+    /// Keeps only guides targeting the listed genes
+    pub fn include_genes(mut self, genes: HashSet<String>) -> Self {
+        self.include_genes = Some(genes);
+        self
+    }
+
+    /// Drops guides targeting the listed genes
+    pub fn exclude_genes(mut self, genes: HashSet<String>) -> Self {
+        self.exclude_genes = genes;
+        self
+    }
+
+    /// Keeps only guides whose name matches the pattern
+    pub fn pattern(mut self, pattern: &str) -> Result<Self, Error> {
+        let pattern = Regex::new(pattern)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+        self.pattern = Some(pattern);
+        Ok(self)
+    }
+
+    /// Still recognizes reads of excluded guides and reports them as off-panel
+    pub fn report_off_panel(mut self, off_panel: bool) -> Self {
+        self.off_panel = off_panel;
+        self
+    }
+
+    /// Whether reads of excluded guides are reported as off-panel
+    pub fn off_panel(&self) -> bool {
+        self.off_panel
+    }
+
+    /// Whether the guide passes the filter
+    pub fn keeps(&self, name: &str, gene: &str) -> bool {
+        self.include_genes.as_ref().is_none_or(|genes| genes.contains(gene))
+            && !self.exclude_genes.contains(gene)
+            && self.pattern.as_ref().is_none_or(|p| p.is_match(name))
+    }
+}
+
+#[test]
+fn test_guide_filter() {
+    let genes = |x: &[&str]| x.iter().map(|s| s.to_string()).collect::<HashSet<_>>();
+    assert!(GuideFilter::default().keeps("A_+_1.23-P1_h1_Top5", "A"));
+
+    let filter = GuideFilter::default()
+        .include_genes(genes(&["A", "B"]))
+        .exclude_genes(genes(&["B"]))
+        .pattern("_Top5$")
+        .unwrap();
+    assert!(filter.keeps("A_+_1.23-P1_h1_Top5", "A"));
+    assert!(!filter.keeps("A_+_1.23-P1_h1_Supp5", "A"));
+    assert!(!filter.keeps("B_+_1.23-P1_h1_Top5", "B"));
+    assert!(!filter.keeps("C_+_1.23-P1_h1_Top5", "C"));
+}
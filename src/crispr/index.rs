@@ -0,0 +1,342 @@
+use std::convert::TryInto;
+use std::{fs::File, io::{BufReader, BufWriter, Error, ErrorKind, Read, Write}, path::{Path, PathBuf}};
+use flate2::Crc;
+use super::check::{GC_BINS, LoadStats};
+
+/// Leading bytes identifying a screenr library index
+pub const INDEX_MAGIC: &[u8; 8] = b"SCRNRIDX";
+
+/// Version of the index layout, bumped whenever the layout changes
+pub const INDEX_VERSION: u32 = 3;
+
+/// Conventional extension of library index files
+pub const INDEX_EXTENSION: &str = ".sidx";
+
+/// A library a pre-built index was built from
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedSource {
+    pub name: String,
+    pub path: Option<PathBuf>,
+    pub checksum: u32
+}
+impl IndexedSource {
+
+    /// Describes a library file along with the checksum of its current contents
+    pub fn from_file(name: &str, path: &Path) -> Result<Self, Error> {
+        Ok(Self {
+            name: name.to_string(),
+            path: Some(path.canonicalize()?),
+            checksum: file_checksum(path)?
+        })
+    }
+
+    /// Describes a library compiled into the binary
+    pub fn from_bytes(name: &str, bytes: &[u8]) -> Self {
+        let mut crc = Crc::new();
+        crc.update(bytes);
+        Self { name: name.to_string(), path: None, checksum: crc.sum() }
+    }
+
+    /// Confirms the library file has not changed since the index was built
+    ///
+    /// Sources which are no longer present (or were embedded) are not checked
+    /// so that indexes can be moved between machines.
+    pub fn check_stale(&self) -> Result<(), Error> {
+        if let Some(path) = self.path.as_ref().filter(|p| p.exists()) {
+            if file_checksum(path)? != self.checksum {
+                return Err(Error::new(ErrorKind::InvalidData, format!(
+                    "library index is stale: '{}' has changed since the index was built (rerun `screenr index`)",
+                    path.display()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A single guide row of an index
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedGuide {
+    pub name: String,
+    pub gene: String,
+    pub source: usize,
+    pub annotations: Vec<String>
+}
+
+/// Serializable contents of a built `Library`
+#[derive(Debug, Clone, Default)]
+pub struct LibraryIndex {
+    pub guide_seq: String,
+    pub spacer_len: usize,
+    pub sources: Vec<IndexedSource>,
+    pub annotation_names: Vec<String>,
+    pub guides: Vec<IndexedGuide>,
+    pub keys: Vec<(u64, usize)>,
+    /// Bases of guides too long to pack into a key
+    pub long_keys: Vec<(String, usize)>,
+    pub stats: LoadStats
+}
+impl LibraryIndex {
+
+    /// Writes the index to a file
+    pub fn save(&self, filename: &str) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Reads an index from a file, rejecting it if any of its sources changed
+    pub fn open(filename: &str) -> Result<Self, Error> {
+        let index = Self::read(BufReader::new(File::open(filename)?))?;
+        for source in index.sources.iter() {
+            source.check_stale()?;
+        }
+        Ok(index)
+    }
+
+    /// Serializes the index
+    ///
+    /// The layout is the magic bytes, the version, the CRC32 and length of
+    /// the payload followed by the little-endian payload itself.
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let mut payload = Vec::new();
+        let buf = &mut payload;
+        put_str(buf, &self.guide_seq);
+        put_len(buf, self.spacer_len);
+
+        put_len(buf, self.sources.len());
+        for source in self.sources.iter() {
+            put_str(buf, &source.name);
+            put_str(buf, &source.path.as_ref().map_or(String::new(), |p| p.display().to_string()));
+            buf.extend_from_slice(&source.checksum.to_le_bytes());
+        }
+
+        put_len(buf, self.annotation_names.len());
+        for name in self.annotation_names.iter() {
+            put_str(buf, name);
+        }
+
+        put_len(buf, self.guides.len());
+        for guide in self.guides.iter() {
+            put_str(buf, &guide.name);
+            put_str(buf, &guide.gene);
+            put_len(buf, guide.source);
+            put_len(buf, guide.annotations.len());
+            for value in guide.annotations.iter() {
+                put_str(buf, value);
+            }
+        }
+
+        put_len(buf, self.keys.len());
+        for (key, guide) in self.keys.iter() {
+            buf.extend_from_slice(&key.to_le_bytes());
+            put_len(buf, *guide);
+        }
+        put_len(buf, self.long_keys.len());
+        for (bases, guide) in self.long_keys.iter() {
+            put_str(buf, bases);
+            put_len(buf, *guide);
+        }
+
+        let stats = &self.stats;
+        for n in [
+            stats.num_records, stats.num_duplicate_names, stats.num_duplicate_seqs,
+            stats.num_missing_anchor, stats.num_unpackable, stats.num_cross_collisions
+        ] {
+            put_len(buf, n);
+        }
+        put_len(buf, stats.spacer_lengths.len());
+        for (len, n) in stats.spacer_lengths.iter() {
+            put_len(buf, *len);
+            put_len(buf, *n);
+        }
+        for n in stats.gc_bins.iter() {
+            put_len(buf, *n);
+        }
+
+        let mut crc = Crc::new();
+        crc.update(&payload);
+        writer.write_all(INDEX_MAGIC)?;
+        writer.write_all(&INDEX_VERSION.to_le_bytes())?;
+        writer.write_all(&crc.sum().to_le_bytes())?;
+        writer.write_all(&(payload.len() as u64).to_le_bytes())?;
+        writer.write_all(&payload)
+    }
+
+    /// Deserializes an index, validating its header and payload checksum
+    pub fn read<R: Read>(mut reader: R) -> Result<Self, Error> {
+        let mut header = [0u8; 24];
+        reader.read_exact(&mut header)
+            .map_err(|_| invalid("file is not a screenr library index"))?;
+        if &header[..8] != INDEX_MAGIC {
+            return Err(invalid("file is not a screenr library index"));
+        }
+        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if version != INDEX_VERSION {
+            return Err(invalid(&format!(
+                "library index version {} is not supported (expected {}); rebuild it with `screenr index`",
+                version, INDEX_VERSION
+            )));
+        }
+        let checksum = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        let payload_len = u64::from_le_bytes(header[16..24].try_into().unwrap());
+
+        let mut payload = Vec::new();
+        reader.take(payload_len).read_to_end(&mut payload)?;
+        let mut crc = Crc::new();
+        crc.update(&payload);
+        if payload.len() as u64 != payload_len || crc.sum() != checksum {
+            return Err(invalid("library index is truncated or corrupted"));
+        }
+
+        let mut cursor = Cursor { buf: &payload, pos: 0 };
+        let mut index = Self {
+            guide_seq: cursor.string()?,
+            spacer_len: cursor.len()?,
+            ..Self::default()
+        };
+
+        for _ in 0..cursor.len()? {
+            let name = cursor.string()?;
+            let path = cursor.string()?;
+            let checksum = u32::from_le_bytes(cursor.bytes(4)?.try_into().unwrap());
+            index.sources.push(IndexedSource {
+                name,
+                path: if path.is_empty() { None } else { Some(PathBuf::from(path)) },
+                checksum
+            });
+        }
+
+        for _ in 0..cursor.len()? {
+            index.annotation_names.push(cursor.string()?);
+        }
+
+        let num_guides = cursor.len()?;
+        index.guides.reserve(num_guides);
+        for _ in 0..num_guides {
+            let name = cursor.string()?;
+            let gene = cursor.string()?;
+            let source = cursor.len()?;
+            let annotations = (0..cursor.len()?)
+                .map(|_| cursor.string())
+                .collect::<Result<Vec<_>, _>>()?;
+            index.guides.push(IndexedGuide { name, gene, source, annotations });
+        }
+
+        let num_keys = cursor.len()?;
+        index.keys.reserve(num_keys);
+        for _ in 0..num_keys {
+            let key = u64::from_le_bytes(cursor.bytes(8)?.try_into().unwrap());
+            index.keys.push((key, cursor.len()?));
+        }
+        for _ in 0..cursor.len()? {
+            let bases = cursor.string()?;
+            index.long_keys.push((bases, cursor.len()?));
+        }
+
+        let stats = &mut index.stats;
+        stats.num_records = cursor.len()?;
+        stats.num_duplicate_names = cursor.len()?;
+        stats.num_duplicate_seqs = cursor.len()?;
+        stats.num_missing_anchor = cursor.len()?;
+        stats.num_unpackable = cursor.len()?;
+        stats.num_cross_collisions = cursor.len()?;
+        for _ in 0..cursor.len()? {
+            let len = cursor.len()?;
+            stats.spacer_lengths.insert(len, cursor.len()?);
+        }
+        for bin in 0..GC_BINS {
+            stats.gc_bins[bin] = cursor.len()?;
+        }
+
+        Ok(index)
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+/// CRC32 of a file's contents
+fn file_checksum(path: &Path) -> Result<u32, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut crc = Crc::new();
+    let mut buffer = [0u8; 1 << 16];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            return Ok(crc.sum());
+        }
+        crc.update(&buffer[..n]);
+    }
+}
+
+fn put_len(buf: &mut Vec<u8>, n: usize) {
+    buf.extend_from_slice(&(n as u32).to_le_bytes());
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    put_len(buf, s.len());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+/// Reads values back out of an index payload
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize
+}
+impl<'a> Cursor<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        let bytes = self.buf.get(self.pos .. self.pos + n)
+            .ok_or_else(|| invalid("library index is truncated or corrupted"))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn len(&mut self) -> Result<usize, Error> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()) as usize)
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let n = self.len()?;
+        String::from_utf8(self.bytes(n)?.to_vec())
+            .map_err(|_| invalid("library index contains invalid text"))
+    }
+}
+
+#[test]
+fn test_index_roundtrip() {
+    let mut index = LibraryIndex {
+        guide_seq: "GTTTAAGAG".to_string(),
+        spacer_len: 19,
+        sources: vec![IndexedSource::from_bytes("h1", b"ACGT")],
+        annotation_names: vec!["pool".to_string()],
+        guides: vec![IndexedGuide {
+            name: "a".to_string(),
+            gene: "A".to_string(),
+            source: 0,
+            annotations: vec!["p1".to_string()]
+        }],
+        keys: vec![(1234, 0)],
+        long_keys: vec![("ACGT".repeat(8), 0)],
+        stats: LoadStats::default()
+    };
+    index.stats.add_spacer(b"ACGTACGTACGTACGTACG");
+
+    let mut bytes = Vec::new();
+    index.write(&mut bytes).unwrap();
+    let read = LibraryIndex::read(bytes.as_slice()).unwrap();
+    assert_eq!(read.spacer_len, 19);
+    assert_eq!(read.guides, index.guides);
+    assert_eq!(read.sources, index.sources);
+    assert_eq!(read.keys, index.keys);
+    assert_eq!(read.long_keys, index.long_keys);
+    assert_eq!(read.stats.spacer_lengths, index.stats.spacer_lengths);
+    assert_eq!(read.stats.gc_bins, index.stats.gc_bins);
+
+    // corrupted payloads are rejected
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    assert!(LibraryIndex::read(bytes.as_slice()).is_err());
+    assert!(LibraryIndex::read(&b"not an index"[..]).is_err());
+}
@@ -1,47 +1,80 @@
-use std::{collections::HashMap, fs::File, io::{Error, Write}};
+use std::{collections::{HashMap, HashSet}, fs::File, io::{BufWriter, Error, Read, Write}};
 use std::time::Instant;
 use indicatif::ProgressBar;
-use regex::Regex;
+use memchr::memmem::{Finder, FinderRev};
 use crate::reader::{FastaGz, FastaRead, FastaRecord, FastqRead, FastqRecord};
-use super::{Fasta, assign_reader, ReaderType, reverse_complement};
+use super::{Fasta, assign_reader, ReaderType, reverse_complement, reverse_complement_into};
+use super::control::ControlSpec;
+use super::filter::GuideFilter;
+use super::check::{LoadStats, find_close_guides, CloseGuides};
+use super::encode::{encode_seq, GuideHashBuilder, GuideKey, MAX_ENCODED_LEN};
+use super::index::{IndexedGuide, IndexedSource, LibraryIndex};
+use super::registry::LibrarySource;
+use super::sheet::{SampleEntry, SampleSheet};
+use super::summary::{SampleStats, write_multiqc};
+use super::tabular::{read_table, TableSpec};
 
 pub struct Library {
-    lib: HashMap<String, String>,
-    counts: HashMap<String, Vec<u32>>,
-    genes: HashMap<String, String>,
-    fwd_regex: Regex,
-    rev_regex: Regex,
-    num_fwd: u32,
-    num_rev: u32,
-    num_total: u32,
-    n_samples: usize
+    lib: HashMap<u64, usize, GuideHashBuilder>,
+    off_panel: HashSet<u64, GuideHashBuilder>,
+    /// Guides too long to pack, keyed on their bases
+    long_lib: HashMap<Vec<u8>, usize>,
+    long_off_panel: HashSet<Vec<u8>>,
+    names: Vec<String>,
+    genes: Vec<String>,
+    annotation_names: Vec<String>,
+    annotations: Vec<Vec<String>>,
+    sources: Vec<String>,
+    source_origins: Vec<Option<LibrarySource>>,
+    guide_sources: Vec<usize>,
+    controls: Vec<bool>,
+    control_spec: ControlSpec,
+    filter: GuideFilter,
+    counts: Vec<u32>,
+    table_spec: TableSpec,
+    load_stats: LoadStats,
+    spacer_len: usize,
+    fwd_finder: Finder<'static>,
+    rev_finder: FinderRev<'static>,
+    stats: SampleStats,
+    sample_stats: Vec<SampleStats>,
+    n_samples: usize,
+    rc_buffer: Vec<u8>
 }
 impl Library {
 
     /// Initializes an empty library
     pub fn new(guide_seq: &str, n_samples: usize) -> Self {
         let rc_guide = reverse_complement(guide_seq);
-        let fwd_regex = Self::build_regex(guide_seq);
-        let rev_regex = Self::build_regex(&rc_guide);
+        let fwd_finder = Finder::new(guide_seq).into_owned();
+        let rev_finder = FinderRev::new(&rc_guide).into_owned();
         Self {
-            lib: HashMap::new(),
-            counts: HashMap::new(),
-            genes: HashMap::new(),
-            fwd_regex, rev_regex,
-            num_fwd: 0,
-            num_rev: 0,
-            num_total: 0,
-            n_samples
+            lib: HashMap::default(),
+            off_panel: HashSet::default(),
+            long_lib: HashMap::new(),
+            long_off_panel: HashSet::new(),
+            names: Vec::new(),
+            genes: Vec::new(),
+            annotation_names: Vec::new(),
+            annotations: Vec::new(),
+            sources: Vec::new(),
+            source_origins: Vec::new(),
+            guide_sources: Vec::new(),
+            controls: Vec::new(),
+            control_spec: ControlSpec::default(),
+            filter: GuideFilter::default(),
+            counts: Vec::new(),
+            table_spec: TableSpec::default(),
+            load_stats: LoadStats::default(),
+            spacer_len: 19,
+            fwd_finder, rev_finder,
+            stats: SampleStats::default(),
+            sample_stats: vec![SampleStats::default(); n_samples],
+            n_samples,
+            rc_buffer: Vec::new()
         } 
     }
 
-    /// Creates the regex expression to match the guide 
-    fn build_regex(seq: &str) -> Regex {
-        let expr = format!(r"{}", seq);
-        Regex::new(&expr)
-            .expect("Error: Could not create regex from seq")
-    }
-
     /// Parses gene information from sequence header
     fn parse_gene(&self, name: &str) -> String {
         name.split("_")
@@ -50,31 +83,206 @@ impl Library {
             .to_string()
     }
 
+    /// Sets the number of protospacer bases preceding the guide (default = 19)
+    pub fn set_spacer_len(&mut self, spacer_len: usize) {
+        self.spacer_len = spacer_len;
+    }
+
+    /// Sets the column layout used when loading tabular libraries
+    pub fn set_table_spec(&mut self, spec: TableSpec) {
+        self.table_spec = spec;
+    }
+
+    /// Sets which guides are designated as controls when loading libraries
+    pub fn set_control_spec(&mut self, spec: ControlSpec) {
+        self.control_spec = spec;
+    }
+
+    /// Sets the subset of guides kept when loading libraries
+    pub fn set_filter(&mut self, filter: GuideFilter) {
+        self.filter = filter;
+    }
+
+    /// Index of the source library guides are currently loaded from
+    fn current_source(&self) -> usize {
+        self.sources.len().saturating_sub(1)
+    }
+
+    /// Builds the name -> guide index mapping of the guides loaded
+    /// from the current source library
+    fn name_index(&self) -> HashMap<String, usize> {
+        let current = self.current_source();
+        self.names
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| self.guide_sources[*idx] == current)
+            .map(|(idx, name)| (name.clone(), idx))
+            .collect()
+    }
+
+    /// Adds a single guide to the library and returns whether it can be matched
+    ///
+    /// Sequences which do not already end in the guide sequence have it appended
+    /// so that spacer-only libraries are keyed the same way as the reads.
+    fn insert_guide(&mut self, name_index: &mut HashMap<String, usize>, name: &str, seq: &str, gene: String, annotations: Vec<String>) -> bool {
+
+        self.load_stats.num_records += 1;
+
+        // excluded guides are only kept to recognize off-panel reads
+        if !self.filter.keeps(name, &gene) {
+            self.load_stats.num_filtered += 1;
+            if self.filter.off_panel() {
+                if let Some(key) = self.guide_key(seq.as_bytes()) {
+                    self.insert_off_panel(key);
+                }
+            }
+            return true;
+        }
+
+        // guides sharing a name share a single row of counts
+        let idx = match name_index.get(name) {
+            Some(idx) => {
+                self.load_stats.num_duplicate_names += 1;
+                *idx
+            },
+            None => {
+                let idx = self.names.len();
+                self.names.push(name.to_string());
+                self.controls.push(self.control_spec.is_control(name, &gene));
+                self.genes.push(gene);
+                self.annotations.push(annotations);
+                self.guide_sources.push(self.current_source());
+                self.counts.extend(std::iter::repeat_n(0, self.n_samples));
+                name_index.insert(name.to_string(), idx);
+                idx
+            }
+        };
+
+        // sequence -> guide index mapping
+        // (guides with non-ACGT bases keep their row but can never be matched)
+        let anchor = self.fwd_finder.needle();
+        if seq.as_bytes().ends_with(anchor) {
+            self.load_stats.add_spacer(&seq.as_bytes()[..seq.len() - anchor.len()]);
+        } else {
+            self.load_stats.num_missing_anchor += 1;
+            self.load_stats.add_spacer(seq.as_bytes());
+        }
+        match self.guide_key(seq.as_bytes()) {
+            Some(key) => {
+                match self.key_guide(&key) {
+                    // sequences shared across libraries keep the first library's guide
+                    Some(prev) if self.guide_sources[prev] != self.guide_sources[idx] => {
+                        self.load_stats.num_cross_collisions += 1;
+                    },
+                    Some(_) => {
+                        self.load_stats.num_duplicate_seqs += 1;
+                        self.insert_key(key, idx);
+                    },
+                    None => {
+                        self.insert_key(key, idx);
+                    }
+                }
+                true
+            },
+            None => {
+                self.load_stats.num_unpackable += 1;
+                false
+            }
+        }
+    }
+
+    /// Key of a library sequence, appending the guide sequence if missing
+    fn guide_key(&self, seq: &[u8]) -> Option<GuideKey> {
+        let anchor = self.fwd_finder.needle();
+        if seq.ends_with(anchor) {
+            GuideKey::new(seq)
+        } else {
+            GuideKey::new(&[seq, anchor].concat())
+        }
+    }
+
+    /// Guide a sequence key is assigned to
+    fn key_guide(&self, key: &GuideKey) -> Option<usize> {
+        match key {
+            GuideKey::Packed(key) => self.lib.get(key).copied(),
+            GuideKey::Bases(bases) => self.long_lib.get(bases).copied()
+        }
+    }
+
+    /// Assigns a sequence key to a guide
+    fn insert_key(&mut self, key: GuideKey, idx: usize) {
+        match key {
+            GuideKey::Packed(key) => self.lib.insert(key, idx),
+            GuideKey::Bases(bases) => self.long_lib.insert(bases, idx)
+        };
+    }
+
+    /// Records the sequence key of an excluded guide
+    fn insert_off_panel(&mut self, key: GuideKey) {
+        match key {
+            GuideKey::Packed(key) => self.off_panel.insert(key),
+            GuideKey::Bases(bases) => self.long_off_panel.insert(bases)
+        };
+    }
+
+    fn warn_unpackable(num_unpackable: usize) {
+        if num_unpackable > 0 {
+            eprintln!("WARNING: {} guide(s) with non-ACGT bases cannot be matched", num_unpackable);
+        }
+    }
+
     /// Parses sequence information from a fasta formatted reader
     fn parse_sequences<R: FastaRead + Iterator<Item = FastaRecord>>(&mut self, reader: R) {
-        for record in reader.into_iter() {
-           
-            // sequence -> name mapping
-            self.lib.insert(
-                record.get_seq().to_string(),
-                record.get_name().to_string(), 
-            );
+        let mut name_index = self.name_index();
+        let mut num_unpackable = 0;
 
-            // name -> counts mapping 
-            self.counts.insert(
-                record.get_name().to_string(),
-                vec![0; self.n_samples]
-            );
+        for record in reader.into_iter() {
+            let gene = self.parse_gene(record.get_name());
+            if !self.insert_guide(&mut name_index, record.get_name(), record.get_seq(), gene, Vec::new()) {
+                num_unpackable += 1;
+            }
+        }
+        Self::warn_unpackable(num_unpackable);
+    }
 
-            // name -> gene mapping
-            self.genes.insert(
-                record.get_name().to_string(), 
-                self.parse_gene(record.get_name())
-            );
+    /// Parses guides from a tabular (CSV/TSV) library
+    fn parse_table(&mut self, filename: &str) -> Result<(), Error> {
+        let table = read_table(filename, &self.table_spec)?;
+
+        // annotation columns are matched by label across libraries
+        let annotation_idx: Vec<usize> = table.annotation_names
+            .iter()
+            .map(|label| {
+                match self.annotation_names.iter().position(|x| x == label) {
+                    Some(idx) => idx,
+                    None => {
+                        self.annotation_names.push(label.to_string());
+                        self.annotation_names.len() - 1
+                    }
+                }
+            })
+            .collect();
+
+        let mut name_index = self.name_index();
+        let mut num_unpackable = 0;
+        for guide in table.guides {
+            let gene = match guide.gene {
+                Some(gene) => gene,
+                None => self.parse_gene(&guide.name)
+            };
+            let mut annotations = vec![String::new(); self.annotation_names.len()];
+            for (idx, value) in annotation_idx.iter().zip(guide.annotations) {
+                annotations[*idx] = value;
+            }
+            if !self.insert_guide(&mut name_index, &guide.name, &guide.seq, gene, annotations) {
+                num_unpackable += 1;
+            }
         }
+        Self::warn_unpackable(num_unpackable);
+        Ok(())
     }
 
-    /// Reads in a FASTA formatted file and initializes library
+    /// Reads in a FASTA or tabular formatted file and initializes library
     pub fn load_library(&mut self, filename: &str) -> Result<Option<bool>, Error> {
 
         match assign_reader(filename) {
@@ -88,50 +296,228 @@ impl Library {
                 self.parse_sequences(fr);
                 Ok(Some(true))
             },
+            Some(ReaderType::TABLE) | Some(ReaderType::TABLEGZ) => {
+                self.parse_table(filename)?;
+                Ok(Some(true))
+            },
+            Some(ReaderType::INDEX) => {
+                let index = LibraryIndex::open(filename)?;
+                self.load_index(index)?;
+                Ok(Some(true))
+            },
             _ => {
                 Ok(None)
             }
         }
     }
 
-    /// Truncate the sequence to the 19bp protospacer
-    fn truncate_seq(&self, seq: &str) -> Option<String> {
-        match self.fwd_regex.find_at(seq, 20) {
-            Some(mat) => {
-                let trunc_seq = &seq[mat.start()-19 .. mat.end()];
-                Some(trunc_seq.to_string())
+    /// Reads in a gzipped FASTA library from any reader
+    pub fn load_fasta_gz<R: Read>(&mut self, reader: R) {
+        let fr = FastaGz::from_reader(reader);
+        self.parse_sequences(fr);
+    }
+
+    /// Reads in a library from a resolved registry source
+    pub fn load_source(&mut self, source: &LibrarySource) -> Result<Option<bool>, Error> {
+        match source {
+            LibrarySource::File(path) => {
+                let filename = path.to_str()
+                    .ok_or_else(|| Error::new(std::io::ErrorKind::InvalidInput, "library path is not valid unicode"))?;
+                self.load_library(filename)
             },
-            _ => None
+            LibrarySource::Embedded(bytes) => {
+                self.load_fasta_gz(*bytes);
+                Ok(Some(true))
+            }
         }
     }
 
+    /// Reads in a library from a resolved registry source, tagging its guides
+    /// with the provided library name
+    ///
+    /// Several libraries may be loaded this way; guides are only merged by name
+    /// within a library and sequences already present in a previously loaded
+    /// library are reported as collisions and left with the first library.
+    pub fn load_source_as(&mut self, name: &str, source: &LibrarySource) -> Result<Option<bool>, Error> {
+        self.sources.push(name.to_string());
+        self.source_origins.push(Some(source.clone()));
+        let num_collisions = self.load_stats.num_cross_collisions;
+        let num_mismatched = self.num_mismatched_spacers();
+        let loaded = self.load_source(source)?;
+        let num_collisions = self.load_stats.num_cross_collisions - num_collisions;
+        if num_collisions > 0 {
+            eprintln!(
+                "WARNING: {} guide sequence(s) in library '{}' are already present in a previously loaded library and will be counted there",
+                num_collisions, name
+            );
+        }
+        let num_mismatched = self.num_mismatched_spacers() - num_mismatched;
+        if num_mismatched > 0 {
+            eprintln!(
+                "WARNING: {} guide(s) in library '{}' have a spacer length other than {}bp and cannot be matched (see --spacer-length)",
+                num_mismatched, name, self.spacer_len
+            );
+        }
+        Ok(loaded)
+    }
+
+    /// Adds the guides of a pre-built library index
+    ///
+    /// The libraries the index was built from replace the tag of the
+    /// `load_source_as` call that loaded it.
+    fn load_index(&mut self, index: LibraryIndex) -> Result<(), Error> {
+        if index.guide_seq.as_bytes() != self.fwd_finder.needle() {
+            return Err(Error::new(std::io::ErrorKind::InvalidInput, format!(
+                "library index was built for guide sequence {}", index.guide_seq
+            )));
+        }
+        if index.spacer_len != self.spacer_len {
+            return Err(Error::new(std::io::ErrorKind::InvalidInput, format!(
+                "library index was built for a spacer length of {}bp", index.spacer_len
+            )));
+        }
+
+        // drop the placeholder tag of the index file itself
+        if !self.sources.is_empty() && !self.guide_sources.contains(&self.current_source()) {
+            self.sources.pop();
+            self.source_origins.pop();
+        }
+        let source_offset = self.sources.len();
+        for source in index.sources {
+            self.sources.push(source.name);
+            self.source_origins.push(source.path.map(LibrarySource::File));
+        }
+
+        let annotation_idx: Vec<usize> = index.annotation_names
+            .iter()
+            .map(|label| {
+                match self.annotation_names.iter().position(|x| x == label) {
+                    Some(idx) => idx,
+                    None => {
+                        self.annotation_names.push(label.to_string());
+                        self.annotation_names.len() - 1
+                    }
+                }
+            })
+            .collect();
+
+        // guides excluded by the filter are dropped (or kept as off-panel keys)
+        let mut guide_map = Vec::with_capacity(index.guides.len());
+        for guide in index.guides {
+            if !self.filter.keeps(&guide.name, &guide.gene) {
+                self.load_stats.num_filtered += 1;
+                guide_map.push(None);
+                continue;
+            }
+            guide_map.push(Some(self.names.len()));
+            let mut annotations = vec![String::new(); self.annotation_names.len()];
+            for (idx, value) in annotation_idx.iter().zip(guide.annotations) {
+                annotations[*idx] = value;
+            }
+            self.controls.push(self.control_spec.is_control(&guide.name, &guide.gene));
+            self.names.push(guide.name);
+            self.genes.push(guide.gene);
+            self.annotations.push(annotations);
+            self.guide_sources.push(guide.source + source_offset);
+            self.counts.extend(std::iter::repeat_n(0, self.n_samples));
+        }
+
+        self.lib.reserve(index.keys.len());
+        let keys = index.keys
+            .into_iter()
+            .map(|(key, guide)| (GuideKey::Packed(key), guide))
+            .chain(index.long_keys.into_iter().map(|(bases, guide)| (GuideKey::Bases(bases.into_bytes()), guide)));
+        for (key, guide) in keys {
+            let guide = match guide_map[guide] {
+                Some(guide) => guide,
+                None => {
+                    if self.filter.off_panel() {
+                        self.insert_off_panel(key);
+                    }
+                    continue;
+                }
+            };
+            match self.key_guide(&key) {
+                Some(_) => self.load_stats.num_cross_collisions += 1,
+                None => self.insert_key(key, guide)
+            }
+        }
+        self.load_stats.merge(&index.stats);
+        Ok(())
+    }
+
+    /// Collects the loaded guides into a serializable index
+    pub fn to_index(&self) -> Result<LibraryIndex, Error> {
+        let sources = self.sources
+            .iter()
+            .zip(self.source_origins.iter())
+            .map(|(name, origin)| match origin {
+                Some(LibrarySource::File(path)) => IndexedSource::from_file(name, path),
+                Some(LibrarySource::Embedded(bytes)) => Ok(IndexedSource::from_bytes(name, bytes)),
+                None => Ok(IndexedSource { name: name.to_string(), path: None, checksum: 0 })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let guides = (0..self.num_guides())
+            .map(|g| IndexedGuide {
+                name: self.names[g].clone(),
+                gene: self.genes[g].clone(),
+                source: self.guide_sources[g],
+                annotations: self.annotations[g].clone()
+            })
+            .collect();
+        Ok(LibraryIndex {
+            guide_seq: String::from_utf8_lossy(self.fwd_finder.needle()).to_string(),
+            spacer_len: self.spacer_len,
+            sources,
+            annotation_names: self.annotation_names.clone(),
+            guides,
+            keys: self.lib.iter().map(|(k, v)| (*k, *v)).collect(),
+            long_keys: self.long_lib.iter().map(|(k, v)| (String::from_utf8_lossy(k).to_string(), *v)).collect(),
+            stats: self.load_stats.clone()
+        })
+    }
+
+    /// Truncate the sequence to the protospacer
+    ///
+    /// `pos` is the first guide sequence match, which is only searched past
+    /// when it leaves no room for a protospacer before it.
+    fn truncate_seq<'a>(&self, seq: &'a [u8], pos: usize) -> Option<&'a [u8]> {
+        let offset = self.spacer_len + 1;
+        let start = if pos >= offset {
+            pos
+        } else {
+            self.fwd_finder.find(seq.get(offset..)?)? + offset
+        };
+        Some(&seq[start - self.spacer_len .. start + self.fwd_finder.needle().len()])
+    }
 
-    /// Determines directionality and truncates string
+
+    /// Determines directionality and truncates sequence
     /// if sequence is valid in library
-    fn get_direction(&mut self, record: &FastqRecord) -> Option<String> {
+    ///
+    /// Reverse complements are written into `rc_buffer`
+    /// so that no allocation happens per read.
+    fn get_direction<'a>(&mut self, seq: &'a [u8], rc_buffer: &'a mut Vec<u8>) -> Option<&'a [u8]> {
 
         // match against the forward guide
-        if self.fwd_regex.is_match(record.get_seq()) {
-            let seq = record.get_seq();
-            match self.truncate_seq(seq) {
-                Some(s) => {
-                    self.num_fwd += 1; 
-                    Some(s)
-                },
-                None => None 
+        if let Some(pos) = self.fwd_finder.find(seq) {
+            let trunc = self.truncate_seq(seq, pos);
+            if trunc.is_some() {
+                self.stats.num_fwd += 1;
             }
+            trunc
         }
 
         // match against the reverse complement of the guide
-        else if self.rev_regex.is_match(record.get_seq()) {
-            let seq = reverse_complement(record.get_seq());
-            match self.truncate_seq(&seq) {
-                Some(s) => {
-                    self.num_rev += 1; 
-                    Some(s)
-                },
-                None => None 
+        // (its last match is the first guide sequence of the reverse complement)
+        else if let Some(rev_pos) = self.rev_finder.rfind(seq) {
+            reverse_complement_into(seq, rc_buffer);
+            let pos = seq.len() - rev_pos - self.fwd_finder.needle().len();
+            let trunc = self.truncate_seq(rc_buffer, pos);
+            if trunc.is_some() {
+                self.stats.num_rev += 1;
             }
+            trunc
         }
 
         // no match
@@ -142,128 +528,324 @@ impl Library {
 
     /// Matches the sequence against the library 
     /// and increments the named key
-    fn match_lib(&mut self, seq: &str, idx: usize) {
-        if self.lib.contains_key(seq) {
-            let name = self.lib.get(seq).unwrap();
-            self.counts.get_mut(name).unwrap()[idx] += 1;
+    fn match_lib(&mut self, seq: &[u8], idx: usize) {
+        if seq.len() > MAX_ENCODED_LEN {
+            self.match_long(seq, idx);
+            return;
+        }
+        let key = match encode_seq(seq) {
+            Some(key) => key,
+            None => return
+        };
+        if let Some(guide) = self.lib.get(&key).copied() {
+            self.count_guide(guide, idx);
+        } else if self.off_panel.contains(&key) {
+            self.stats.num_off_panel += 1;
         }
     } 
 
-    /// Matches the sequence against the library
-    pub fn match_seq(&mut self, record: &FastqRecord, idx: usize) {
-        match self.get_direction(record) {
-            Some(seq) => self.match_lib(&seq, idx),
-            _ => ()
+    /// Matches a sequence too long to pack against the guides keyed on their bases
+    fn match_long(&mut self, seq: &[u8], idx: usize) {
+        let upper;
+        let seq = if seq.iter().any(u8::is_ascii_lowercase) {
+            upper = seq.to_ascii_uppercase();
+            &upper[..]
+        } else {
+            seq
         };
+        if let Some(guide) = self.long_lib.get(seq).copied() {
+            self.count_guide(guide, idx);
+        } else if self.long_off_panel.contains(seq) {
+            self.stats.num_off_panel += 1;
+        }
     }
 
-    /// Prints the count table to stdout
-    pub fn print_count_table(&self, names: Vec<&str>) {
-        print!("sgRNA\tGene");
-        for l in names {
-            print!("\t{}", l);
+    /// Increments the count of a matched guide
+    fn count_guide(&mut self, guide: usize, idx: usize) {
+        self.counts[guide * self.n_samples + idx] += 1;
+        self.stats.num_assigned += 1;
+        if self.controls[guide] {
+            self.stats.num_control += 1;
+        }
+    }
+
+    /// Matches a raw sequence against the library
+    pub fn match_seq_bytes(&mut self, seq: &[u8], idx: usize) {
+        let mut rc_buffer = std::mem::take(&mut self.rc_buffer);
+        if let Some(trunc) = self.get_direction(seq, &mut rc_buffer) {
+            self.match_lib(trunc, idx);
         }
-        print!("\n");
+        self.rc_buffer = rc_buffer;
+    }
 
-        self.counts
-            .keys()
-            .for_each(|k| {
-                let gene = self.genes.get(k).unwrap();
-                let counts = self.counts.get(k).unwrap();
+    /// Matches the sequence against the library
+    pub fn match_seq(&mut self, record: &FastqRecord, idx: usize) {
+        self.match_seq_bytes(record.get_seq().as_bytes(), idx);
+    }
 
-                print!("{}\t{}", k, gene);
-                for c in counts.iter() {
-                    print!("\t{}", c);
-                }
-                print!("\n");
-            });
+    /// Number of distinct guides in the library
+    pub fn num_guides(&self) -> usize {
+        self.names.len()
     }
 
-    /// Writes the count table to file
-    pub fn write_count_table(&mut self, filename: &str, names: Vec<&str>) -> Result<(), Error> {
+    /// Name of the guide at the provided index
+    pub fn get_name(&self, guide: usize) -> &str {
+        &self.names[guide]
+    }
 
-        // open file
-        let mut file = File::create(filename)
-            .expect("Unable to create file");
-        
+    /// Gene of the guide at the provided index
+    pub fn get_gene(&self, guide: usize) -> &str {
+        &self.genes[guide]
+    }
+
+    /// Name of the library the guide at the provided index was loaded from
+    pub fn get_source(&self, guide: usize) -> Option<&str> {
+        self.sources
+            .get(self.guide_sources[guide])
+            .map(|x| x.as_str())
+    }
+
+    /// Whether the guide at the provided index is a control guide
+    pub fn is_control(&self, guide: usize) -> bool {
+        self.controls[guide]
+    }
+
+    /// Indices of the control guides of the library
+    pub fn control_guides(&self) -> Vec<usize> {
+        (0..self.num_guides())
+            .filter(|g| self.controls[*g])
+            .collect()
+    }
+
+    /// Number of libraries loaded with `load_source_as`
+    pub fn num_sources(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Per-sample counts of the guide at the provided index
+    pub fn get_counts(&self, guide: usize) -> &[u32] {
+        let start = guide * self.n_samples;
+        &self.counts[start .. start + self.n_samples]
+    }
+
+    /// Number of distinct genes targeted by the library
+    pub fn num_genes(&self) -> usize {
+        self.genes
+            .iter()
+            .collect::<std::collections::HashSet<_>>()
+            .len()
+    }
+
+    /// Statistics accumulated while loading the library
+    pub fn load_stats(&self) -> &LoadStats {
+        &self.load_stats
+    }
+
+    /// Number of loaded guides whose spacer length differs from the matched length
+    pub fn num_mismatched_spacers(&self) -> usize {
+        self.load_stats.spacer_lengths
+            .iter()
+            .filter(|(len, _)| **len != self.spacer_len)
+            .map(|(_, n)| n)
+            .sum()
+    }
+
+    /// Finds pairs of library sequences within `max_mismatches` of each other
+    ///
+    /// Sequences are only kept once, so the guides duplicating an earlier
+    /// sequence are added as pairs at distance 0. Guides too long to pack
+    /// are not compared.
+    pub fn close_guides(&self, max_mismatches: usize) -> CloseGuides {
+        let mut close = find_close_guides(
+            self.lib.keys().copied(),
+            self.fwd_finder.needle().len(),
+            max_mismatches
+        );
+        let num_duplicates = self.load_stats.num_duplicate_seqs + self.load_stats.num_cross_collisions;
+        if num_duplicates > 0 {
+            close.pairs[0] = num_duplicates;
+            close.min_distance = Some(0);
+        }
+        close
+    }
+
+    /// Annotation values of the guide at the provided index
+    pub fn get_annotations(&self, guide: usize) -> Vec<&str> {
+        (0..self.annotation_names.len())
+            .map(|idx| {
+                self.annotations[guide]
+                    .get(idx)
+                    .map_or("", |x| x.as_str())
+            })
+            .collect()
+    }
+
+    /// Writes the count table to any writer
+    fn write_table<W: Write>(&self, writer: &mut W, names: Vec<&str>) -> Result<(), Error> {
 
         // write header
-        file.write_all("sgRNA\tGene".as_bytes())?;
+        let tag_sources = self.num_sources() > 1;
+        write!(writer, "sgRNA\tGene")?;
+        if tag_sources {
+            write!(writer, "\tLibrary")?;
+        }
+        for l in self.annotation_names.iter() {
+            write!(writer, "\t{}", l)?;
+        }
         for l in names {
-            file.write_all(format!("\t{}", l).as_bytes())?;
+            write!(writer, "\t{}", l)?;
         }
-        file.write_all("\n".as_bytes())?;
+        writeln!(writer)?;
 
         // write counts
-        self.counts
-            .keys()
-            .for_each(|k| {
-                let gene = self.genes.get(k).unwrap();
-                let counts = self.counts.get(k).unwrap();
-
-                file.write_all(format!("{}\t{}", k, gene).as_bytes()).unwrap();
-                for c in counts.iter() {
-                    file.write_all(format!("\t{}", c).as_bytes()).unwrap();
-                }
-                file.write_all("\n".as_bytes()).unwrap();
-            });
+        for k in 0..self.num_guides() {
+            write!(writer, "{}\t{}", self.get_name(k), self.get_gene(k))?;
+            if tag_sources {
+                write!(writer, "\t{}", self.get_source(k).unwrap_or(""))?;
+            }
+            for a in self.get_annotations(k) {
+                write!(writer, "\t{}", a)?;
+            }
+            for c in self.get_counts(k) {
+                write!(writer, "\t{}", c)?;
+            }
+            writeln!(writer)?;
+        }
+        writer.flush()
+    }
 
-        Ok(())
+    /// Prints the count table to stdout
+    pub fn print_count_table(&self, names: Vec<&str>) -> Result<(), Error> {
+        let stdout = std::io::stdout();
+        let mut writer = BufWriter::new(stdout.lock());
+        self.write_table(&mut writer, names)
+    }
+
+    /// Writes the count table to file
+    pub fn write_count_table(&mut self, filename: &str, names: Vec<&str>) -> Result<(), Error> {
+
+        // open file
+        let file = File::create(filename)
+            .expect("Unable to create file");
+        let mut writer = BufWriter::new(file);
+        self.write_table(&mut writer, names)
     }
 
     /// Summary statistics on forward/reverse/total reads
     pub fn summary(&self) {
         eprintln!("---");
-        eprintln!("Fwd Matches:\t{}", self.num_fwd);
-        eprintln!("Rev Matches:\t{}", self.num_rev);
-        eprintln!("Total Matches:\t{}", self.num_fwd + self.num_rev);
-        eprintln!("Total Processed:\t{}", self.num_total);
+        eprintln!("Fwd Matches:\t{}", self.stats.num_fwd);
+        eprintln!("Rev Matches:\t{}", self.stats.num_rev);
+        eprintln!("Total Matches:\t{}", self.stats.num_matches());
+        eprintln!("Guide Matches:\t{}", self.stats.num_assigned);
+        eprintln!("Control Matches:\t{}", self.stats.num_control);
+        eprintln!("Control Fraction:\t{:.4}", self.stats.control_fraction());
+        if self.filter.off_panel() {
+            eprintln!("Off-panel Matches:\t{}", self.stats.num_off_panel);
+        }
+        eprintln!("Total Processed:\t{}", self.stats.num_total);
         eprintln!("---");
     }
 
-    fn clear_summary(&mut self) {
-        self.num_fwd = 0;
-        self.num_rev = 0;
-        self.num_total = 0;
+    /// Writes the matching statistics of each sample as MultiQC custom content
+    pub fn write_multiqc(&self, filename: &str, sheet: &SampleSheet) -> Result<(), Error> {
+        let samples: Vec<(&SampleEntry, &SampleStats)> = sheet.samples.iter()
+            .zip(self.sample_stats.iter())
+            .collect();
+        let mut writer = BufWriter::new(File::create(filename)?);
+        write_multiqc(&mut writer, &samples, self.filter.off_panel())?;
+        writer.flush()
+    }
+
+    /// Matching statistics of a sample accumulated over its readers
+    pub fn sample_stats(&self, idx: usize) -> &SampleStats {
+        &self.sample_stats[idx]
+    }
+
+    /// Stores the statistics of the current reader with its sample and resets them
+    fn clear_summary(&mut self, idx: usize) {
+        self.sample_stats[idx].merge(&self.stats);
+        self.stats = SampleStats::default();
     }
 
     /// Match all sequences in a given reader
-    pub fn match_reader<R: FastqRead + Iterator<Item = FastqRecord>>(&mut self, reader: &mut R, idx: usize) {
+    pub fn match_reader<R: FastqRead + ?Sized>(&mut self, reader: &mut R, idx: usize) {
 
         // confirms that the provided index
         // is not above the maximum expected 
         assert!(idx < self.n_samples);
 
-        // show progress bar
-        let pb = ProgressBar::new_spinner();
-        pb.enable_steady_tick(100);
+        let pb = Self::progress_bar();
+        let start_time = Instant::now();
+
+        while let Some(record) = reader.next_record_ref() {
+            self.match_seq_bytes(record.get_seq(), idx);
+            self.stats.num_total += 1;
+            self.update_progress(&pb, &start_time, false);
+        }
+        self.update_progress(&pb, &start_time, true);
+        pb.finish();
+
+        self.summary();
+        self.clear_summary(idx);
+    }
+
+    /// Match all read pairs of two mate readers
+    ///
+    /// The guide is searched for in the first mate and only if it is
+    /// missing in the second, so that each pair is counted at most once.
+    pub fn match_paired_reader<R1: FastqRead + ?Sized, R2: FastqRead + ?Sized>(&mut self, r1: &mut R1, r2: &mut R2, idx: usize) {
+        assert!(idx < self.n_samples);
 
-        // keep track of elapsed time
+        let pb = Self::progress_bar();
         let start_time = Instant::now();
 
-        reader
-            .into_iter()
-            .for_each(|x| {
-                self.match_seq(&x, idx);
-                self.num_total += 1;
-
-                // update progress bar
-                if self.num_total % 10000 == 0 {
-                    pb.set_message(&format!(
-                        "Processing... {} records // {:.2} sec elapsed", 
-                        self.num_total, 
-                        start_time.elapsed().as_secs_f32()
-                        ));
+        loop {
+            match (r1.next_record_ref(), r2.next_record_ref()) {
+                (Some(mate1), Some(mate2)) => {
+                    self.match_pair_bytes(mate1.get_seq(), mate2.get_seq(), idx);
+                },
+                (None, None) => break,
+                _ => {
+                    eprintln!("WARNING: mate files have a different number of records; unpaired records were skipped");
+                    break;
                 }
-            });
-        pb.set_message(&format!(
-            "Processing... {} records // {:.2} sec elapsed",
-            self.num_total,
-            start_time.elapsed().as_secs_f32()
-            ));
+            }
+            self.stats.num_total += 1;
+            self.update_progress(&pb, &start_time, false);
+        }
+        self.update_progress(&pb, &start_time, true);
         pb.finish();
 
         self.summary();
-        self.clear_summary();
+        self.clear_summary(idx);
+    }
+
+    /// Matches a read pair, falling back to the second mate when the first lacks the guide sequence
+    pub fn match_pair_bytes(&mut self, mate1: &[u8], mate2: &[u8], idx: usize) {
+        let mut rc_buffer = std::mem::take(&mut self.rc_buffer);
+        if let Some(trunc) = self.get_direction(mate1, &mut rc_buffer) {
+            self.match_lib(trunc, idx);
+        } else if let Some(trunc) = self.get_direction(mate2, &mut rc_buffer) {
+            self.match_lib(trunc, idx);
+        }
+        self.rc_buffer = rc_buffer;
+    }
+
+    fn progress_bar() -> ProgressBar {
+        let pb = ProgressBar::new_spinner();
+        pb.enable_steady_tick(100);
+        pb
+    }
+
+    /// Updates the progress bar every 10000 records (or when forced)
+    fn update_progress(&self, pb: &ProgressBar, start_time: &Instant, force: bool) {
+        if force || self.stats.num_total.is_multiple_of(10000) {
+            pb.set_message(&format!(
+                "Processing... {} records // {:.2} sec elapsed", 
+                self.stats.num_total, 
+                start_time.elapsed().as_secs_f32()
+                ));
+        }
     }
 }
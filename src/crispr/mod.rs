@@ -1,7 +1,25 @@
+pub mod check;
+pub mod control;
+pub mod encode;
+pub mod filter;
+pub mod index;
 pub mod library;
+pub mod registry;
+pub mod sheet;
+pub mod summary;
+pub mod tabular;
 pub mod utils;
 
+pub use check::{CloseGuides, LoadStats, find_close_guides};
+pub use control::{ControlSpec, DEFAULT_CONTROL_PATTERN};
+pub use encode::{encode_seq, decode_seq};
+pub use filter::{GuideFilter, read_name_list};
+pub use index::{IndexedGuide, IndexedSource, LibraryIndex, INDEX_EXTENSION};
 pub use library::Library;
+pub use registry::{BUNDLED_LIBRARIES, LibraryConfig, LibrarySource, RegisteredLibrary, load_user_libraries, resolve_library, resolve_registered};
+pub use sheet::{SampleEntry, SampleSheet, sample_name};
+pub use summary::{MULTIQC_SUFFIX, SampleStats, write_multiqc};
+pub use tabular::{Column, TableSpec};
 pub use crate::reader::Fasta;
 
-pub use utils::{assign_reader, ReaderType, reverse_complement};
+pub use utils::{assign_reader, ReaderType, reverse_complement, reverse_complement_into};
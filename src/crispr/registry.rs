@@ -0,0 +1,304 @@
+use std::{collections::BTreeMap, env, fs, io::{Error, ErrorKind}, path::{Path, PathBuf}};
+use serde::Deserialize;
+use super::tabular::{Column, TableSpec};
+
+/// Environment variable listing additional library directories (`:` separated)
+pub const LIBRARY_PATH_VAR: &str = "SCREENR_LIBRARY_PATH";
+
+/// Environment variable pointing at a user library registry file
+pub const LIBRARY_CONFIG_VAR: &str = "SCREENR_LIBRARIES";
+
+/// A library which ships with screenr and can be referred to by name
+#[derive(Debug)]
+pub struct BundledLibrary {
+    pub name: &'static str,
+    pub filename: &'static str,
+    pub description: &'static str
+}
+
+/// Libraries shipped in `data/libraries`
+pub const BUNDLED_LIBRARIES: &[BundledLibrary] = &[
+    BundledLibrary {
+        name: "all",
+        filename: "CRISPRi_v2_crop28.all.fasta.gz",
+        description: "Complete CRISPRi v2 library (sublibraries h1-h7)"
+    },
+    BundledLibrary {
+        name: "h1",
+        filename: "CRISPRi_v2_crop28.h1.fasta.gz",
+        description: "CRISPRi v2 sublibrary h1"
+    },
+    BundledLibrary {
+        name: "h2",
+        filename: "CRISPRi_v2_crop28.h2.fasta.gz",
+        description: "CRISPRi v2 sublibrary h2"
+    },
+    BundledLibrary {
+        name: "h3",
+        filename: "CRISPRi_v2_crop28.h3.fasta.gz",
+        description: "CRISPRi v2 sublibrary h3"
+    },
+    BundledLibrary {
+        name: "h4",
+        filename: "CRISPRi_v2_crop28.h4.fasta.gz",
+        description: "CRISPRi v2 sublibrary h4"
+    },
+    BundledLibrary {
+        name: "h5",
+        filename: "CRISPRi_v2_crop28.h5.fasta.gz",
+        description: "CRISPRi v2 sublibrary h5"
+    },
+    BundledLibrary {
+        name: "h6",
+        filename: "CRISPRi_v2_crop28.h6.fasta.gz",
+        description: "CRISPRi v2 sublibrary h6"
+    },
+    BundledLibrary {
+        name: "h7",
+        filename: "CRISPRi_v2_crop28.h7.fasta.gz",
+        description: "CRISPRi v2 sublibrary h7"
+    },
+];
+
+/// Settings of a library registered in the user's `libraries.toml`
+///
+/// Each table of the registry file maps an alias to its settings:
+///
+/// ```toml
+/// [mylib]
+/// path = "libraries/mylib.csv"
+/// description = "In-house kinase library"
+/// guide = "GTTTTAGAGC"
+/// spacer_length = 20
+/// seq_column = "sequence"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LibraryConfig {
+    pub path: String,
+    pub description: Option<String>,
+    pub guide: Option<String>,
+    pub spacer_length: Option<usize>,
+    pub id_column: Option<String>,
+    pub seq_column: Option<String>,
+    pub gene_column: Option<String>,
+    #[serde(default)]
+    pub annotation_columns: Vec<String>,
+    pub header: Option<bool>
+}
+
+impl LibraryConfig {
+    /// Column layout of the library with unset columns left at their defaults
+    pub fn table_spec(&self) -> TableSpec {
+        let mut spec = TableSpec::default();
+        if let Some(id) = &self.id_column {
+            spec.id = Column::parse(id);
+        }
+        if let Some(seq) = &self.seq_column {
+            spec.seq = Column::parse(seq);
+        }
+        if let Some(gene) = &self.gene_column {
+            spec.gene = Some(Column::parse(gene));
+        }
+        spec.annotations = self.annotation_columns
+            .iter()
+            .map(|x| Column::parse(x))
+            .collect();
+        spec.header = self.header;
+        spec
+    }
+}
+
+/// A library resolved by name along with any user-registered settings
+#[derive(Debug, Clone)]
+pub struct RegisteredLibrary {
+    pub source: LibrarySource,
+    pub config: Option<LibraryConfig>
+}
+
+/// Where a library is loaded from
+#[derive(Debug, Clone)]
+pub enum LibrarySource {
+    File(PathBuf),
+    Embedded(&'static [u8])
+}
+impl LibrarySource {
+    /// Human readable location of the library
+    pub fn describe(&self) -> String {
+        match self {
+            Self::File(path) => path.display().to_string(),
+            Self::Embedded(_) => "embedded".to_string()
+        }
+    }
+}
+
+/// Gzipped FASTA bytes of a bundled library compiled into the binary
+#[cfg(feature = "embed-libraries")]
+fn embedded_bytes(name: &str) -> Option<&'static [u8]> {
+    macro_rules! embed {
+        ($file:literal) => {
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/data/libraries/", $file))
+        };
+    }
+    let bytes: &'static [u8] = match name {
+        "all" => embed!("CRISPRi_v2_crop28.all.fasta.gz"),
+        "h1" => embed!("CRISPRi_v2_crop28.h1.fasta.gz"),
+        "h2" => embed!("CRISPRi_v2_crop28.h2.fasta.gz"),
+        "h3" => embed!("CRISPRi_v2_crop28.h3.fasta.gz"),
+        "h4" => embed!("CRISPRi_v2_crop28.h4.fasta.gz"),
+        "h5" => embed!("CRISPRi_v2_crop28.h5.fasta.gz"),
+        "h6" => embed!("CRISPRi_v2_crop28.h6.fasta.gz"),
+        "h7" => embed!("CRISPRi_v2_crop28.h7.fasta.gz"),
+        _ => return None
+    };
+    Some(bytes)
+}
+
+#[cfg(not(feature = "embed-libraries"))]
+fn embedded_bytes(_name: &str) -> Option<&'static [u8]> {
+    None
+}
+
+/// Directories searched for libraries, in order of precedence
+///
+/// Entries of `$SCREENR_LIBRARY_PATH` come first, followed by the user
+/// data directory (`$XDG_DATA_HOME/screenr/libraries` or
+/// `~/.local/share/screenr/libraries`).
+pub fn library_search_path() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = env::var_os(LIBRARY_PATH_VAR)
+        .map(|paths| env::split_paths(&paths).collect())
+        .unwrap_or_default();
+
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")));
+    if let Some(data_home) = data_home {
+        dirs.push(data_home.join("screenr").join("libraries"));
+    }
+    dirs
+}
+
+/// Location of the user library registry, if one exists
+///
+/// `$SCREENR_LIBRARIES` takes precedence over
+/// `$XDG_CONFIG_HOME/screenr/libraries.toml` (or `~/.config/screenr/libraries.toml`).
+pub fn user_config_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(LIBRARY_CONFIG_VAR) {
+        return Some(PathBuf::from(path));
+    }
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    let path = config_home.join("screenr").join("libraries.toml");
+    if path.exists() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Parses a library registry, resolving relative paths against `base_dir`
+pub fn parse_user_libraries(text: &str, base_dir: &Path) -> Result<BTreeMap<String, LibraryConfig>, Error> {
+    let mut libraries: BTreeMap<String, LibraryConfig> = toml::from_str(text)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+    for config in libraries.values_mut() {
+        let relative = base_dir.join(&config.path);
+        if Path::new(&config.path).is_relative() && relative.exists() {
+            config.path = relative.display().to_string();
+        }
+    }
+    Ok(libraries)
+}
+
+/// Reads the user library registry (empty if none is configured)
+pub fn load_user_libraries() -> Result<BTreeMap<String, LibraryConfig>, Error> {
+    match user_config_path() {
+        Some(path) => {
+            let text = fs::read_to_string(&path)
+                .map_err(|e| Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+            let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+            parse_user_libraries(&text, base_dir)
+                .map_err(|e| Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+        },
+        None => Ok(BTreeMap::new())
+    }
+}
+
+/// Looks up a bundled library by name
+pub fn find_bundled(name: &str) -> Option<&'static BundledLibrary> {
+    BUNDLED_LIBRARIES
+        .iter()
+        .find(|lib| lib.name == name)
+}
+
+/// Resolves a library path, user alias or bundled name along with its settings
+///
+/// User aliases take precedence over bundled names of the same name.
+pub fn resolve_registered(name: &str) -> Result<Option<RegisteredLibrary>, Error> {
+    if !Path::new(name).exists() {
+        if let Some(config) = load_user_libraries()?.remove(name) {
+            let source = resolve_library(&config.path)
+                .ok_or_else(|| Error::new(
+                    ErrorKind::NotFound,
+                    format!("library '{}' points to missing path {}", name, config.path)
+                ))?;
+            return Ok(Some(RegisteredLibrary { source, config: Some(config) }));
+        }
+    }
+    Ok(resolve_library(name).map(|source| RegisteredLibrary { source, config: None }))
+}
+
+/// Resolves a library path or bundled name to its source
+///
+/// Existing paths are used as-is. Otherwise the name (or, for bundled
+/// libraries, their filename) is searched for along the library search
+/// path, then in the embedded libraries, and finally in the source
+/// checkout the binary was built from.
+pub fn resolve_library(name: &str) -> Option<LibrarySource> {
+    if Path::new(name).exists() {
+        return Some(LibrarySource::File(PathBuf::from(name)));
+    }
+
+    let bundled = find_bundled(name);
+    let filename = bundled.map_or(name, |lib| lib.filename);
+    let search_path = library_search_path();
+    if let Some(path) = search_path.iter().map(|dir| dir.join(filename)).find(|p| p.exists()) {
+        return Some(LibrarySource::File(path));
+    }
+
+    bundled?;
+    if let Some(bytes) = embedded_bytes(name) {
+        return Some(LibrarySource::Embedded(bytes));
+    }
+    let checkout = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("data/libraries")
+        .join(filename);
+    if checkout.exists() {
+        Some(LibrarySource::File(checkout))
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_parse_user_libraries() {
+    let text = r#"
+        [mylib]
+        path = "mylib.csv"
+        guide = "GTTTTAGAGC"
+        spacer_length = 20
+        annotation_columns = ["set"]
+
+        [panel]
+        path = "/abs/panel.fasta"
+    "#;
+    let libraries = parse_user_libraries(text, Path::new("/nonexistent"))
+        .expect("Unable to parse registry");
+    assert_eq!(libraries.len(), 2);
+    assert_eq!(libraries["mylib"].spacer_length, Some(20));
+    assert_eq!(libraries["mylib"].annotation_columns, vec!["set"]);
+    assert_eq!(libraries["panel"].guide, None);
+
+    assert!(parse_user_libraries("[x]\nguide = \"A\"", Path::new(".")).is_err());
+    assert!(parse_user_libraries("[x]\npath = \"a\"\nspacer = 3", Path::new(".")).is_err());
+}
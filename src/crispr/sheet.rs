@@ -0,0 +1,226 @@
+use std::{collections::{HashMap, HashSet}, fs::File, io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write}, path::Path, sync::OnceLock};
+use regex::Regex;
+use super::summary::SampleStats;
+use super::tabular::{split_fields, table_delimiter};
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Extensions stripped from fastq filenames to derive sample names
+const FASTQ_EXTENSIONS: [&str; 4] = [".fastq", ".fq", ".fastq.gz", ".fq.gz"];
+
+/// Illumina sample/lane/read/chunk suffixes (e.g. `_S1_L001_R1_001`)
+const ILLUMINA_SUFFIX: &str = r"(?:_S\d+)?(?:_L\d{3})?_[RI][12](?:_\d{3})?$|_S\d+(?:_L\d{3})?$";
+
+/// Compiled `ILLUMINA_SUFFIX`, built on first use
+static ILLUMINA_SUFFIX_REGEX: OnceLock<Regex> = OnceLock::new();
+
+/// Derives a sample name from a fastq filename
+///
+/// The directory, fastq/compression extensions and Illumina suffixes are
+/// removed, e.g. `runs/T0_rep1_S1_L001_R1_001.fastq.gz` becomes `T0_rep1`.
+pub fn sample_name(filename: &str) -> String {
+    let base = Path::new(filename)
+        .file_name()
+        .map_or(filename.to_string(), |x| x.to_string_lossy().to_string());
+    let stem = FASTQ_EXTENSIONS.iter()
+        .filter(|ext| base.ends_with(*ext))
+        .map(|ext| &base[..base.len() - ext.len()])
+        .min_by_key(|stem| stem.len())
+        .unwrap_or(&base);
+    let suffix = ILLUMINA_SUFFIX_REGEX.get_or_init(|| Regex::new(ILLUMINA_SUFFIX).unwrap());
+    let name = suffix.replace(stem, "");
+    if name.is_empty() {
+        stem.to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// A sample of a sample sheet
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SampleEntry {
+    pub name: String,
+    pub r1: String,
+    pub r2: Option<String>,
+    pub condition: Option<String>,
+    pub replicate: Option<String>,
+    pub barcode: Option<String>
+}
+
+/// Samples of a run along with their fastqs and metadata
+///
+/// Sheets are CSV (`*.csv`) or TSV files with a header naming at least the
+/// `sample` and `r1` columns; `r2`, `condition`, `replicate` and `barcode`
+/// are optional. Relative fastq paths are resolved against the directory of
+/// the sheet.
+#[derive(Debug, Clone, Default)]
+pub struct SampleSheet {
+    pub samples: Vec<SampleEntry>
+}
+impl SampleSheet {
+
+    /// Reads a sample sheet
+    pub fn read(filename: &str) -> Result<Self, Error> {
+        let delimiter = table_delimiter(filename);
+        let mut lines = BufReader::new(File::open(filename)?)
+            .lines()
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'));
+
+        let header: Vec<String> = match lines.next() {
+            Some((_, line)) => split_fields(line.trim_end_matches('\r'), delimiter)
+                .iter()
+                .map(|x| x.trim().to_lowercase())
+                .collect(),
+            None => return Err(invalid(format!("sample sheet '{}' is empty", filename)))
+        };
+        let column = |names: &[&str]| header.iter().position(|h| names.contains(&h.as_str()));
+        let name_col = column(&["sample", "name"])
+            .ok_or_else(|| invalid(format!("sample sheet '{}' has no 'sample' column", filename)))?;
+        let r1_col = column(&["r1", "fastq", "input"])
+            .ok_or_else(|| invalid(format!("sample sheet '{}' has no 'r1' column", filename)))?;
+        let r2_col = column(&["r2"]);
+        let condition_col = column(&["condition"]);
+        let replicate_col = column(&["replicate"]);
+        let barcode_col = column(&["barcode"]);
+
+        let base = Path::new(filename).parent().unwrap_or_else(|| Path::new(""));
+        let resolve = |path: &str| base.join(path).to_string_lossy().to_string();
+
+        let mut sheet = Self::default();
+        let mut names = HashSet::new();
+        for (line_idx, line) in lines {
+            let fields = split_fields(line.trim_end_matches('\r'), delimiter);
+            if fields.len() != header.len() {
+                return Err(invalid(format!(
+                    "line {} of '{}' has {} fields but the header has {}",
+                    line_idx + 1, filename, fields.len(), header.len()
+                )));
+            }
+            let field = |col: Option<usize>| col
+                .map(|c| fields[c].trim().to_string())
+                .filter(|x| !x.is_empty());
+
+            let name = field(Some(name_col))
+                .ok_or_else(|| invalid(format!("line {} of '{}' has no sample name", line_idx + 1, filename)))?;
+            if !names.insert(name.clone()) {
+                return Err(invalid(format!("sample '{}' is listed more than once in '{}'", name, filename)));
+            }
+            let r1 = field(Some(r1_col))
+                .ok_or_else(|| invalid(format!("sample '{}' of '{}' has no r1 fastq", name, filename)))?;
+            sheet.samples.push(SampleEntry {
+                name,
+                r1: resolve(&r1),
+                r2: field(r2_col).map(|x| resolve(&x)),
+                condition: field(condition_col),
+                replicate: field(replicate_col),
+                barcode: field(barcode_col)
+            });
+        }
+        if sheet.samples.is_empty() {
+            return Err(invalid(format!("sample sheet '{}' lists no samples", filename)));
+        }
+        Ok(sheet)
+    }
+
+    /// Builds a sheet of single-end inputs, deriving names from the filenames when not provided
+    ///
+    /// Fails if two inputs end up with the same sample name.
+    pub fn from_inputs(inputs: &[&str], names: Option<&[&str]>) -> Result<Self, Error> {
+        let names: Vec<String> = match names {
+            Some(names) => names.iter().map(|x| x.to_string()).collect(),
+            None => inputs.iter().map(|x| sample_name(x)).collect()
+        };
+        let mut seen: HashMap<&str, &str> = HashMap::new();
+        for (name, input) in names.iter().zip(inputs) {
+            if let Some(other) = seen.insert(name, input) {
+                return Err(Error::new(ErrorKind::InvalidInput, format!(
+                    "'{}' and '{}' share the sample name '{}' (provide unique --names or a sample sheet)",
+                    other, input, name
+                )));
+            }
+        }
+        Ok(Self {
+            samples: names.into_iter()
+                .zip(inputs)
+                .map(|(name, input)| SampleEntry { name, r1: input.to_string(), ..SampleEntry::default() })
+                .collect()
+        })
+    }
+
+    /// Sample names in sheet order
+    pub fn names(&self) -> Vec<&str> {
+        self.samples.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    /// Replicate groups of the samples (their condition, or the sample itself when missing)
+    pub fn groups(&self) -> Vec<&str> {
+        self.samples.iter()
+            .map(|s| s.condition.as_deref().unwrap_or(&s.name))
+            .collect()
+    }
+
+    /// Writes the metadata of each sample alongside its matching statistics
+    pub fn write_summary(&self, filename: &str, stats: &[SampleStats]) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writeln!(writer, "Sample\tCondition\tReplicate\tBarcode\tR1\tR2\tReads\tAssigned\tMappingRate\tControlFraction")?;
+        for (sample, stats) in self.samples.iter().zip(stats) {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.6}\t{:.6}",
+                sample.name,
+                sample.condition.as_deref().unwrap_or(""),
+                sample.replicate.as_deref().unwrap_or(""),
+                sample.barcode.as_deref().unwrap_or(""),
+                sample.r1,
+                sample.r2.as_deref().unwrap_or(""),
+                stats.num_total, stats.num_assigned,
+                stats.mapping_rate(), stats.control_fraction()
+            )?;
+        }
+        writer.flush()
+    }
+}
+
+#[test]
+fn test_sample_name() {
+    assert_eq!(sample_name("data/example/subset0000.fastq.gz"), "subset0000");
+    assert_eq!(sample_name("runs/T0_rep1_S1_L001_R1_001.fastq.gz"), "T0_rep1");
+    assert_eq!(sample_name("T0_rep1_S12_R2_001.fq"), "T0_rep1");
+    assert_eq!(sample_name("end_R1.fq.gz"), "end");
+    assert_eq!(sample_name("end_S3.fastq"), "end");
+    assert_eq!(sample_name("lib_001.fastq.gz"), "lib_001");
+    assert_eq!(sample_name("R1.fastq"), "R1");
+
+    let sheet = SampleSheet::from_inputs(&["a/x_R1.fq", "b/y_R1.fq"], None).unwrap();
+    assert_eq!(sheet.names(), vec!["x", "y"]);
+    assert!(SampleSheet::from_inputs(&["a/x_L001_R1_001.fq", "a/x_L002_R1_001.fq"], None).is_err());
+    assert!(SampleSheet::from_inputs(&["a.fq", "b.fq"], Some(&["s", "s"])).is_err());
+}
+
+#[test]
+fn test_sample_sheet() {
+    let dir = std::env::temp_dir().join(format!("screenr_sheet_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let filename = dir.join("sheet.csv");
+    std::fs::write(
+        &filename,
+        "Sample,R1,R2,Condition,Replicate\n# comment\nT0_1,t0.fq.gz,,T0,1\nEnd_1,end_R1.fq.gz,end_R2.fq.gz,End,1\n"
+    ).unwrap();
+    let sheet = SampleSheet::read(filename.to_str().unwrap()).unwrap();
+    assert_eq!(sheet.names(), vec!["T0_1", "End_1"]);
+    assert_eq!(sheet.groups(), vec!["T0", "End"]);
+    assert_eq!(sheet.samples[0].r1, dir.join("t0.fq.gz").to_string_lossy());
+    assert_eq!(sheet.samples[0].r2, None);
+    assert_eq!(sheet.samples[1].r2.as_deref(), Some(dir.join("end_R2.fq.gz").to_string_lossy().as_ref()));
+    assert_eq!(sheet.samples[1].barcode, None);
+
+    // duplicate samples are rejected
+    std::fs::write(&filename, "sample\tr1\na\ta.fq\na\tb.fq\n").unwrap();
+    assert!(SampleSheet::read(filename.to_str().unwrap()).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
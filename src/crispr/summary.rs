@@ -0,0 +1,160 @@
+use std::io::{Error, Write};
+use super::sheet::SampleEntry;
+
+/// Suffix MultiQC expects of custom content files
+pub const MULTIQC_SUFFIX: &str = "_mqc.json";
+
+/// Read matching statistics of a sample
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SampleStats {
+    pub num_fwd: u32,
+    pub num_rev: u32,
+    pub num_assigned: u32,
+    pub num_control: u32,
+    pub num_off_panel: u32,
+    pub num_total: u32
+}
+impl SampleStats {
+
+    /// Adds the statistics of another run over the same sample
+    pub fn merge(&mut self, other: &Self) {
+        self.num_fwd += other.num_fwd;
+        self.num_rev += other.num_rev;
+        self.num_assigned += other.num_assigned;
+        self.num_control += other.num_control;
+        self.num_off_panel += other.num_off_panel;
+        self.num_total += other.num_total;
+    }
+
+    /// Number of reads containing the guide sequence in either direction
+    pub fn num_matches(&self) -> u32 {
+        self.num_fwd + self.num_rev
+    }
+
+    /// Fraction of processed reads assigned to a guide
+    pub fn mapping_rate(&self) -> f64 {
+        self.num_assigned as f64 / self.num_total.max(1) as f64
+    }
+
+    /// Fraction of assigned reads assigned to a control guide
+    pub fn control_fraction(&self) -> f64 {
+        self.num_control as f64 / self.num_assigned.max(1) as f64
+    }
+}
+
+/// MultiQC formats of count and percentage columns (text columns have none)
+const COUNT_FORMAT: &str = ", \"format\": \"{:,.0f}\"";
+const PERCENT_FORMAT: &str = ", \"suffix\": \"%\", \"min\": 0, \"max\": 100, \"format\": \"{:,.2f}\"";
+
+/// Columns of the MultiQC table: key, title, description and format
+const MULTIQC_COLUMNS: [(&str, &str, &str, &str); 9] = [
+    ("condition", "Condition", "Condition of the sample in the sample sheet", ""),
+    ("replicate", "Replicate", "Replicate of the sample in the sample sheet", ""),
+    ("reads", "Reads", "Number of reads processed", COUNT_FORMAT),
+    ("guide_sequence", "Guide Sequence", "Number of reads containing the guide sequence in either direction", COUNT_FORMAT),
+    ("percent_guide_sequence", "% Guide Sequence", "Percentage of reads containing the guide sequence", PERCENT_FORMAT),
+    ("assigned", "Assigned", "Number of reads assigned to a library guide", COUNT_FORMAT),
+    ("percent_assigned", "% Assigned", "Percentage of reads assigned to a library guide", PERCENT_FORMAT),
+    ("percent_control", "% Control", "Percentage of assigned reads assigned to control guides", PERCENT_FORMAT),
+    ("off_panel", "Off-panel", "Number of reads matching guides removed by the library filters", COUNT_FORMAT)
+];
+
+/// Writes per-sample statistics as a MultiQC custom content table
+///
+/// The condition and replicate columns are only included when a sample has
+/// them and the off-panel column only when off-panel reads were tracked.
+pub fn write_multiqc<W: Write>(writer: &mut W, samples: &[(&SampleEntry, &SampleStats)], off_panel: bool) -> Result<(), Error> {
+    let has_condition = samples.iter().any(|(entry, _)| entry.condition.is_some());
+    let has_replicate = samples.iter().any(|(entry, _)| entry.replicate.is_some());
+    let columns: Vec<_> = MULTIQC_COLUMNS.iter()
+        .filter(|c| match c.0 {
+            "condition" => has_condition,
+            "replicate" => has_replicate,
+            "off_panel" => off_panel,
+            _ => true
+        })
+        .collect();
+
+    writeln!(writer, "{{")?;
+    writeln!(writer, "  \"id\": \"screenr\",")?;
+    writeln!(writer, "  \"section_name\": \"screenr\",")?;
+    writeln!(writer, "  \"description\": \"Guide matching statistics of each sample\",")?;
+    writeln!(writer, "  \"plot_type\": \"table\",")?;
+    writeln!(writer, "  \"pconfig\": {{\"id\": \"screenr_table\", \"title\": \"screenr: guide matching\"}},")?;
+    writeln!(writer, "  \"headers\": {{")?;
+    for (idx, (key, title, description, format)) in columns.iter().enumerate() {
+        writeln!(
+            writer,
+            "    {}: {{\"title\": {}, \"description\": {}{}}}{}",
+            json_str(key), json_str(title), json_str(description), format,
+            if idx + 1 < columns.len() { "," } else { "" }
+        )?;
+    }
+    writeln!(writer, "  }},")?;
+    writeln!(writer, "  \"data\": {{")?;
+    for (idx, (entry, stats)) in samples.iter().enumerate() {
+        let values: Vec<String> = columns.iter()
+            .map(|(key, ..)| {
+                let value = match *key {
+                    "condition" => json_str(entry.condition.as_deref().unwrap_or("")),
+                    "replicate" => json_str(entry.replicate.as_deref().unwrap_or("")),
+                    "reads" => stats.num_total.to_string(),
+                    "guide_sequence" => stats.num_matches().to_string(),
+                    "percent_guide_sequence" => format!("{:.4}", 100.0 * stats.num_matches() as f64 / stats.num_total.max(1) as f64),
+                    "assigned" => stats.num_assigned.to_string(),
+                    "percent_assigned" => format!("{:.4}", 100.0 * stats.mapping_rate()),
+                    "percent_control" => format!("{:.4}", 100.0 * stats.control_fraction()),
+                    _ => stats.num_off_panel.to_string()
+                };
+                format!("{}: {}", json_str(key), value)
+            })
+            .collect();
+        writeln!(
+            writer,
+            "    {}: {{{}}}{}",
+            json_str(&entry.name), values.join(", "),
+            if idx + 1 < samples.len() { "," } else { "" }
+        )?;
+    }
+    writeln!(writer, "  }}")?;
+    writeln!(writer, "}}")
+}
+
+/// Quotes and escapes a JSON string
+fn json_str(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c)
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[test]
+fn test_multiqc() {
+    let stats = SampleStats { num_fwd: 6, num_rev: 2, num_assigned: 5, num_control: 1, num_total: 10, ..SampleStats::default() };
+    let entry = |name: &str| SampleEntry { name: name.to_string(), ..SampleEntry::default() };
+    let mut json = Vec::new();
+    write_multiqc(&mut json, &[(&entry("s\"1"), &stats), (&entry("s2"), &SampleStats::default())], false).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.contains("\"s\\\"1\": {\"reads\": 10, \"guide_sequence\": 8, \"percent_guide_sequence\": 80.0000, \"assigned\": 5, \"percent_assigned\": 50.0000, \"percent_control\": 20.0000},"));
+    assert!(json.contains("\"s2\": {\"reads\": 0,"));
+    assert!(!json.contains("off_panel"));
+    assert!(!json.contains("condition"));
+
+    // sample sheet metadata leads each row
+    let t0 = SampleEntry { condition: Some("T0".to_string()), replicate: Some("1".to_string()), ..entry("t0") };
+    let mut json = Vec::new();
+    write_multiqc(&mut json, &[(&t0, &stats), (&entry("s2"), &stats)], false).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.contains("\"t0\": {\"condition\": \"T0\", \"replicate\": \"1\", \"reads\": 10,"));
+    assert!(json.contains("\"s2\": {\"condition\": \"\", \"replicate\": \"\", \"reads\": 10,"));
+}
@@ -0,0 +1,277 @@
+use std::{fs::File, io::{BufRead, BufReader, Error, ErrorKind, Read}};
+use flate2::read::MultiGzDecoder;
+
+/// Identifies a column of a tabular library by header name or position
+#[derive(Debug, Clone, PartialEq)]
+pub enum Column {
+    Name(String),
+    Index(usize)
+}
+impl Column {
+
+    /// Parses a 1-based column position or a header name
+    pub fn parse(s: &str) -> Self {
+        match s.parse::<usize>() {
+            Ok(pos) if pos > 0 => Self::Index(pos - 1),
+            _ => Self::Name(s.to_string())
+        }
+    }
+
+    /// Resolves the column to a field index given the (optional) header
+    fn resolve(&self, header: Option<&[String]>) -> Result<usize, Error> {
+        match (self, header) {
+            (Self::Index(idx), Some(header)) if *idx >= header.len() => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("column {} is out of range: the library header has {} columns", idx + 1, header.len())
+            )),
+            (Self::Index(idx), _) => Ok(*idx),
+            (Self::Name(name), Some(header)) => header
+                .iter()
+                .position(|x| x.eq_ignore_ascii_case(name))
+                .ok_or_else(|| invalid(format!("column '{}' not found in library header", name))),
+            (Self::Name(name), None) => Err(invalid(format!(
+                "column '{}' is referenced by name but the library has no header", name
+            )))
+        }
+    }
+
+    /// Header label used for the column in the count table
+    fn label(&self, header: Option<&[String]>, idx: usize) -> String {
+        match (self, header) {
+            (Self::Name(name), _) => name.to_string(),
+            (Self::Index(_), Some(header)) => header[idx].to_string(),
+            (Self::Index(pos), None) => format!("column_{}", pos + 1)
+        }
+    }
+}
+
+/// Column holding genes when none is given, if the library has it
+const DEFAULT_GENE_COLUMN: usize = 2;
+
+/// Describes how guides are laid out in a delimited (CSV/TSV) library file
+///
+/// Without a gene column, genes are read from the third column when the
+/// library has one (and it is not the id or sequence column) and are
+/// otherwise derived from the sgRNA ids.
+#[derive(Debug, Clone)]
+pub struct TableSpec {
+    pub id: Column,
+    pub seq: Column,
+    pub gene: Option<Column>,
+    pub annotations: Vec<Column>,
+    pub header: Option<bool>
+}
+impl Default for TableSpec {
+    fn default() -> Self {
+        Self {
+            id: Column::Index(0),
+            seq: Column::Index(1),
+            gene: None,
+            annotations: Vec::new(),
+            header: None
+        }
+    }
+}
+
+/// A single guide parsed from a tabular library
+#[derive(Debug)]
+pub struct TableGuide {
+    pub name: String,
+    pub seq: String,
+    pub gene: Option<String>,
+    pub annotations: Vec<String>
+}
+
+/// All guides of a tabular library along with the annotation labels
+#[derive(Debug)]
+pub struct TableLibrary {
+    pub annotation_names: Vec<String>,
+    pub guides: Vec<TableGuide>
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Infers the field delimiter from the file extension
+pub fn table_delimiter(filename: &str) -> char {
+    let stem = filename.trim_end_matches(".gz");
+    if stem.ends_with(".csv") {
+        ','
+    } else {
+        '\t'
+    }
+}
+
+/// Splits a delimited line into fields, honoring double-quoted fields
+pub fn split_fields(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            },
+            '"' => in_quotes = !in_quotes,
+            c if c == delimiter && !in_quotes => {
+                fields.push(field.trim().to_string());
+                field.clear();
+            },
+            c => field.push(c)
+        }
+    }
+    fields.push(field.trim().to_string());
+    fields
+}
+
+/// Whether a field looks like a nucleotide sequence
+fn is_sequence(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b"ACGTNacgtn".contains(&b))
+}
+
+/// Reads a tabular library from any reader
+pub fn parse_table<R: BufRead>(reader: R, delimiter: char, spec: &TableSpec) -> Result<TableLibrary, Error> {
+    let mut rows = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        rows.push(split_fields(line.trim_end_matches('\r'), delimiter));
+    }
+
+    // detect a header unless told explicitly
+    let has_header = match spec.header {
+        Some(has_header) => has_header,
+        None => match (rows.first(), &spec.seq) {
+            (Some(first), Column::Index(idx)) => !first.get(*idx).is_some_and(|x| is_sequence(x)),
+            (Some(_), Column::Name(_)) => true,
+            (None, _) => false
+        }
+    };
+    let header = if has_header && !rows.is_empty() {
+        Some(rows.remove(0))
+    } else {
+        None
+    };
+    let header = header.as_deref();
+
+    // resolve columns against the header
+    let id_idx = spec.id.resolve(header)?;
+    let seq_idx = spec.seq.resolve(header)?;
+    let gene_idx = match spec.gene.as_ref() {
+        Some(gene) => Some(gene.resolve(header)?),
+        None => {
+            let width = header.or(rows.first().map(|x| x.as_slice())).map_or(0, |x| x.len());
+            Some(DEFAULT_GENE_COLUMN)
+                .filter(|idx| *idx < width && *idx != id_idx && *idx != seq_idx)
+        }
+    };
+    let annotation_idx = spec.annotations
+        .iter()
+        .map(|x| x.resolve(header))
+        .collect::<Result<Vec<usize>, Error>>()?;
+    let annotation_names = spec.annotations
+        .iter()
+        .zip(annotation_idx.iter())
+        .map(|(x, idx)| x.label(header, *idx))
+        .collect();
+
+    let offset = if header.is_some() { 2 } else { 1 };
+    let guides = rows
+        .into_iter()
+        .enumerate()
+        .map(|(line_num, row)| {
+            let field = |idx: usize| row.get(idx)
+                .cloned()
+                .ok_or_else(|| invalid(format!(
+                    "library row {} has no column {}", line_num + offset, idx + 1
+                )));
+            Ok(TableGuide {
+                name: field(id_idx)?,
+                seq: field(seq_idx)?.to_ascii_uppercase(),
+                gene: gene_idx.map(field).transpose()?,
+                annotations: annotation_idx
+                    .iter()
+                    .map(|idx| field(*idx))
+                    .collect::<Result<Vec<String>, Error>>()?
+            })
+        })
+        .collect::<Result<Vec<TableGuide>, Error>>()?;
+
+    Ok(TableLibrary { annotation_names, guides })
+}
+
+/// Reads a (optionally gzipped) tabular library file
+pub fn read_table(filename: &str, spec: &TableSpec) -> Result<TableLibrary, Error> {
+    let file = File::open(filename)?;
+    let reader: Box<dyn Read> = if filename.ends_with(".gz") {
+        Box::new(MultiGzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    parse_table(BufReader::new(reader), table_delimiter(filename), spec)
+}
+
+#[test]
+fn test_split_fields() {
+    assert_eq!(split_fields("a,b ,c", ','), vec!["a", "b", "c"]);
+    assert_eq!(split_fields("\"a,1\",\"b \"\"x\"\"\",", ','), vec!["a,1", "b \"x\"", ""]);
+    assert_eq!(split_fields("a\tb", '\t'), vec!["a", "b"]);
+}
+
+#[test]
+fn test_parse_table_header_detection() {
+    let text = "id,sequence,gene,set\ng1,ACGTACGT,A1BG,1\ng2,TTTTACGT,NT,2\n";
+    let spec = TableSpec {
+        annotations: vec![Column::Name("set".to_string())],
+        ..TableSpec::default()
+    };
+    let table = parse_table(text.as_bytes(), ',', &spec).expect("Unable to parse table");
+    assert_eq!(table.annotation_names, vec!["set"]);
+    assert_eq!(table.guides.len(), 2);
+    assert_eq!(table.guides[1].name, "g2");
+    assert_eq!(table.guides[1].gene.as_deref(), Some("NT"));
+    assert_eq!(table.guides[1].annotations, vec!["2"]);
+
+    let headerless = "g1\tacgt\tA1BG\n";
+    let table = parse_table(headerless.as_bytes(), '\t', &TableSpec::default())
+        .expect("Unable to parse table");
+    assert_eq!(table.guides[0].seq, "ACGT");
+    assert_eq!(table.guides[0].gene.as_deref(), Some("A1BG"));
+}
+
+#[test]
+fn test_parse_table_columns() {
+    // two-column libraries derive genes from the ids
+    let text = "seq,id
+ACGTACGT,A1BG_1
+";
+    let spec = TableSpec {
+        id: Column::Index(1),
+        seq: Column::Index(0),
+        ..TableSpec::default()
+    };
+    let table = parse_table(text.as_bytes(), ',', &spec).expect("Unable to parse table");
+    assert_eq!(table.guides[0].name, "A1BG_1");
+    assert_eq!(table.guides[0].gene, None);
+
+    // an explicit gene column must exist
+    let spec = TableSpec { gene: Some(Column::Index(2)), ..spec };
+    assert!(parse_table(text.as_bytes(), ',', &spec).is_err());
+
+    // out of range columns are rejected rather than indexed
+    let text = "id,sequence,gene
+g1,ACGTACGT,A1BG
+";
+    let spec = TableSpec {
+        annotations: vec![Column::Index(9)],
+        ..TableSpec::default()
+    };
+    let err = parse_table(text.as_bytes(), ',', &spec).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
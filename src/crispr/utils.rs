@@ -1,4 +1,4 @@
-use regex::Regex;
+use super::index::INDEX_EXTENSION;
 
 /// Perform a reverse complement on a given sequence
 pub fn reverse_complement(s: &str) -> String {
@@ -15,32 +15,78 @@ pub fn reverse_complement(s: &str) -> String {
         .collect()
 }
 
+/// Perform a reverse complement on a byte sequence, writing into a reusable buffer
+pub fn reverse_complement_into(s: &[u8], buffer: &mut Vec<u8>) {
+    buffer.clear();
+    buffer.extend(
+        s.iter()
+            .rev()
+            .map(|x| {
+                match x {
+                    b'A' => b'T',
+                    b'T' => b'A',
+                    b'G' => b'C',
+                    b'C' => b'G',
+                    _ => *x
+                }})
+    );
+}
+
 /// Defines the different reader formats
+#[allow(clippy::upper_case_acronyms)]
 pub enum ReaderType {
     FASTQ, FASTQGZ,
-    FASTA, FASTAGZ
+    FASTA, FASTAGZ,
+    TABLE, TABLEGZ,
+    INDEX
 }
 
 /// Assigns a filetype to specific reader format
 pub fn assign_reader(s: &str) -> Option<ReaderType> {
-    let re_fastq_gz = Regex::new(r".fastq.gz$|.fq.gz$").expect("Could not compile regex");
-    let re_fasta_gz = Regex::new(r".fasta.gz$|.fa.gz$").expect("Could not compile regex");
-    let re_fastq = Regex::new(r".fastq$|.fq$").expect("Could not compile regex");
-    let re_fasta = Regex::new(r".fasta$|.fa$").expect("Could not compile regex");
+    let has_suffix = |suffixes: &[&str]| suffixes.iter().any(|x| s.ends_with(x));
 
-    if re_fastq_gz.is_match(s) {
+    if has_suffix(&[".fastq.gz", ".fq.gz"]) {
         Some(ReaderType::FASTQGZ)
     }
-    else if re_fasta_gz.is_match(s) {
+    else if has_suffix(&[".fasta.gz", ".fa.gz"]) {
         Some(ReaderType::FASTAGZ)
     }
-    else if re_fastq.is_match(s) {
+    else if has_suffix(&[".fastq", ".fq"]) {
         Some(ReaderType::FASTQ)
     }
-    else if re_fasta.is_match(s) {
+    else if has_suffix(&[".fasta", ".fa"]) {
         Some(ReaderType::FASTA)
     }
+    else if has_suffix(&[".csv.gz", ".tsv.gz"]) {
+        Some(ReaderType::TABLEGZ)
+    }
+    else if has_suffix(&[".csv", ".tsv"]) {
+        Some(ReaderType::TABLE)
+    }
+    else if has_suffix(&[INDEX_EXTENSION]) {
+        Some(ReaderType::INDEX)
+    }
     else {
         None
     }
 }
+
+#[test]
+fn test_assign_reader() {
+    assert!(matches!(assign_reader("a/b.fastq.gz"), Some(ReaderType::FASTQGZ)));
+    assert!(matches!(assign_reader("b.fq.gz"), Some(ReaderType::FASTQGZ)));
+    assert!(matches!(assign_reader("b.fa.gz"), Some(ReaderType::FASTAGZ)));
+    assert!(matches!(assign_reader("b.fq"), Some(ReaderType::FASTQ)));
+    assert!(matches!(assign_reader("b.fasta"), Some(ReaderType::FASTA)));
+    assert!(matches!(assign_reader("b.csv.gz"), Some(ReaderType::TABLEGZ)));
+    assert!(matches!(assign_reader("b.tsv"), Some(ReaderType::TABLE)));
+    assert!(matches!(assign_reader("b.sidx"), Some(ReaderType::INDEX)));
+    assert!(assign_reader("b.fastq.bz2").is_none());
+}
+
+#[test]
+fn test_reverse_complement_into() {
+    let mut buffer = Vec::new();
+    reverse_complement_into(b"GTTTAAGAG", &mut buffer);
+    assert_eq!(buffer, reverse_complement("GTTTAAGAG").as_bytes());
+}
@@ -1,2 +1,3 @@
 pub mod reader;
 pub mod crispr;
+pub mod analysis;
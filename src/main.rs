@@ -1,67 +1,793 @@
-mod reader;
-mod crispr;
+use screenr::crispr::{BUNDLED_LIBRARIES, Column, ControlSpec, DEFAULT_CONTROL_PATTERN, GuideFilter, INDEX_EXTENSION, read_name_list, Library, LibraryConfig, LibrarySource, MULTIQC_SUFFIX, RegisteredLibrary, SampleEntry, SampleSheet, SampleStats, TableSpec, assign_reader, load_user_libraries, resolve_library, resolve_registered, ReaderType};
+use std::{fs::File, io::{BufWriter, Error, ErrorKind, Write}};
+use screenr::analysis::{Concordance, CorrelationMethod, CountTable, GeneStats, GuideComparison, MergedTable, NormMethod, Phenotype, QcReport, Rng, compare_groups, gene_statistics, nb_test, normalize, rank_genes, size_factors, write_comparisons, write_gene_stats, write_guide_phenotypes, write_nb_tests, write_rra, write_size_factors};
+use screenr::reader::{Fastq, FastqGz, FastqRead};
 
-use crispr::{Library, assign_reader, ReaderType};
-use reader::{Fastq, FastqGz};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
-use std::collections::HashSet;
-use std::path::Path;
-use clap::{App, Arg};
-
-fn get_args() -> App<'static, 'static> {
-    App::new("Screenr")
-        .version("0.3")
-        .author("Noam Teyssier <Noam.Teyssier@ucsf.edu>")
-        .about("Parses a provided fastq file for a required guide then matches sequences into a provided sgRNA library to determine sgRNA counts")
-        .arg(Arg::with_name("INPUT")
-            .short("i")
-            .long("input")
-            .help("Sets the input fastq(s) file to use (*.fastq, *.fq, *.fastq.gz, *.fq.gz)")
-            .required(true)
-            .min_values(1))
-        .arg(Arg::with_name("LIBRARY")
+/// Arguments describing how to load a guide library
+fn library_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("LIBRARY")
             .short("l")
             .long("library")
-            .help("Sets the input fasta file to use as a guide library [can also provide 'all' or 'h[1..7]' without path]")
-            .required(true)
-            .takes_value(true))
-        .arg(Arg::with_name("OUTPUT")
-            .short("o")
-            .long("output")
-            .help("Sets the output tsv to write guide counts to (default = stdout)")
-            .takes_value(true))
-        .arg(Arg::with_name("NAMES")
-            .short("n")
-            .long("names")
-            .help("Sets the sample name for file(s)")
+            .help("Sets the input fasta/csv/tsv file(s) to use as a guide library [can also provide registered names such as 'all' or 'h[1..7]']")
             .required(true)
-            .min_values(1))
-        .arg(Arg::with_name("GUIDE")
+            .min_values(1),
+        Arg::with_name("GUIDE")
             .short("g")
             .long("guide")
             .help("Sets the input guide sequence to match on")
             .required(false)
             .takes_value(true)
-            .default_value("GTTTAAGAG"))
+            .default_value("GTTTAAGAG"),
+        Arg::with_name("SPACER_LENGTH")
+            .long("spacer-length")
+            .help("Sets the number of protospacer bases preceding the guide sequence")
+            .takes_value(true)
+            .default_value("19"),
+        Arg::with_name("ID_COLUMN")
+            .long("id-column")
+            .help("Sets the sgRNA id column of a tabular library (header name or 1-based position)")
+            .takes_value(true)
+            .default_value("1"),
+        Arg::with_name("SEQ_COLUMN")
+            .long("seq-column")
+            .help("Sets the sequence column of a tabular library (header name or 1-based position)")
+            .takes_value(true)
+            .default_value("2"),
+        Arg::with_name("GENE_COLUMN")
+            .long("gene-column")
+            .help("Sets the gene column of a tabular library (header name or 1-based position) [default: 3 when present, otherwise derived from the sgRNA id]")
+            .takes_value(true),
+        Arg::with_name("ANNOTATION_COLUMNS")
+            .long("annotation-columns")
+            .help("Sets extra columns of a tabular library to carry through to the count table")
+            .takes_value(true)
+            .min_values(1),
+        Arg::with_name("HEADER")
+            .long("header")
+            .help("Treats the first row of a tabular library as a header [default: auto-detect]")
+            .conflicts_with("NO_HEADER"),
+        Arg::with_name("NO_HEADER")
+            .long("no-header")
+            .help("Treats the first row of a tabular library as a guide [default: auto-detect]"),
+        Arg::with_name("INCLUDE_GENES")
+            .long("include-genes")
+            .help("Sets a file listing genes (one per line) to restrict the library to")
+            .takes_value(true),
+        Arg::with_name("EXCLUDE_GENES")
+            .long("exclude-genes")
+            .help("Sets a file listing genes (one per line) to remove from the library")
+            .takes_value(true),
+        Arg::with_name("INCLUDE_PATTERN")
+            .long("include-pattern")
+            .help("Sets a regular expression guide names must match to be kept (e.g. '_h3_' or 'Top5$')")
+            .takes_value(true),
+        Arg::with_name("OFF_PANEL")
+            .long("off-panel")
+            .help("Reports the number of reads matching guides removed by the library filters")
+    ]
+}
+
+/// Arguments designating control guides (shared by every subcommand)
+fn control_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("CONTROL_PATTERN")
+            .long("control-pattern")
+            .help("Sets the regular expression matched against genes to designate control guides")
+            .takes_value(true)
+            .default_value(DEFAULT_CONTROL_PATTERN)
+            .global(true),
+        Arg::with_name("CONTROLS")
+            .long("controls")
+            .help("Sets a file listing control guide names or genes (one per line) [replaces the default control pattern]")
+            .takes_value(true)
+            .global(true)
+    ]
+}
+
+/// Rejects pseudocounts which are not non-negative numbers
+fn validate_pseudocount(value: String) -> Result<(), String> {
+    match value.parse::<f64>() {
+        Ok(x) if x >= 0.0 => Ok(()),
+        _ => Err(format!("pseudocount must be a non-negative number (got '{}')", value))
+    }
+}
+
+/// Rejects guide counts which are not positive integers
+fn validate_positive(value: String) -> Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(x) if x >= 1 => Ok(()),
+        _ => Err(format!("must be a positive integer (got '{}')", value))
+    }
+}
+
+/// Arguments selecting and normalizing the samples of a count table to compare
+fn comparison_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("COUNTS")
+            .short("c")
+            .long("counts")
+            .help("Sets the count table (as written by screenr) to compare samples of")
+            .required(true)
+            .takes_value(true),
+        Arg::with_name("REFERENCE")
+            .short("r")
+            .long("reference")
+            .help("Sets the reference sample name(s) (e.g. T0 replicates)")
+            .required(true)
+            .min_values(1),
+        Arg::with_name("TREATMENT")
+            .short("t")
+            .long("treatment")
+            .help("Sets the treatment sample name(s) (e.g. endpoint replicates)")
+            .required(true)
+            .min_values(1),
+        Arg::with_name("NORMALIZE")
+            .long("normalize")
+            .help("Sets the method used to normalize samples before comparing them")
+            .takes_value(true)
+            .possible_values(&["cpm", "median-ratio", "control"])
+            .default_value("median-ratio"),
+        Arg::with_name("PSEUDOCOUNT")
+            .long("pseudocount")
+            .help("Sets the pseudocount added to every count before normalizing")
+            .takes_value(true)
+            .validator(validate_pseudocount)
+            .default_value("1")
+    ]
+}
+
+fn get_args() -> App<'static, 'static> {
+    App::new("Screenr")
+        .version("0.3")
+        .author("Noam Teyssier <Noam.Teyssier@ucsf.edu>")
+        .about("Maps CRISPRi/a screen sequencing reads to their sgRNA library and analyzes the resulting counts")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .args(&control_args())
+        .subcommand(SubCommand::with_name("count")
+            .about("Parses fastq file(s) for a required guide then matches sequences into a provided sgRNA library to determine sgRNA counts")
+            .arg(Arg::with_name("INPUT")
+                .short("i")
+                .long("input")
+                .help("Sets the input fastq(s) file to use (*.fastq, *.fq, *.fastq.gz, *.fq.gz)")
+                .required_unless("SAMPLE_SHEET")
+                .min_values(1))
+            .arg(Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .help("Sets the output tsv to write guide counts to (default = stdout)")
+                .takes_value(true))
+            .arg(Arg::with_name("NAMES")
+                .short("n")
+                .long("names")
+                .help("Sets the sample name for file(s) [default: derived from the filenames]")
+                .min_values(1))
+            .arg(Arg::with_name("SAMPLE_SHEET")
+                .short("s")
+                .long("sample-sheet")
+                .help("Sets a csv/tsv sample sheet (sample, r1 and optional r2, condition, replicate, barcode columns) to use instead of --input/--names")
+                .takes_value(true)
+                .conflicts_with_all(&["INPUT", "NAMES"]))
+            .arg(Arg::with_name("SAMPLE_OUTPUT")
+                .long("sample-output")
+                .help("Sets the output tsv to write sample metadata and read statistics to [default: *.samples.tsv beside the --output or --normalized-output of a sample sheet]")
+                .takes_value(true))
+            .args(&library_args())
+            .arg(Arg::with_name("NORMALIZE")
+                .long("normalize")
+                .help("Sets the method used to write a normalized count table alongside the raw counts")
+                .takes_value(true)
+                .possible_values(&["cpm", "median-ratio", "control"])
+                .requires("NORMALIZED_OUTPUT"))
+            .arg(Arg::with_name("NORMALIZED_OUTPUT")
+                .long("normalized-output")
+                .help("Sets the output tsv to write normalized counts to (size factors are written to *.size_factors.tsv)")
+                .takes_value(true)
+                .requires("NORMALIZE"))
+            .arg(Arg::with_name("PSEUDOCOUNT")
+                .long("pseudocount")
+                .help("Sets the pseudocount added to every count before normalizing")
+                .takes_value(true)
+                .validator(validate_pseudocount)
+                .default_value("0"))
+            .arg(Arg::with_name("QC_OUTPUT")
+                .long("qc-output")
+                .help("Sets the output tsv to write the correlations of every sample pair to")
+                .takes_value(true))
+            .arg(Arg::with_name("REPORT")
+                .long("report")
+                .help("Sets the output html to write a self-contained QC report to")
+                .takes_value(true))
+            .arg(Arg::with_name("MULTIQC")
+                .long("multiqc")
+                .help("Sets the output MultiQC custom content file to write per-sample statistics to (e.g. screenr_mqc.json)")
+                .takes_value(true)))
+        .subcommand(SubCommand::with_name("library")
+            .alias("libraries")
+            .about("Inspects the libraries available by name")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("list")
+                .about("Lists registered library names, guide counts and descriptions"))
+            .subcommand(SubCommand::with_name("check")
+                .about("Reports design statistics and potential problems of a library")
+                .args(&library_args())
+                .arg(Arg::with_name("MAX_MISMATCHES")
+                    .short("m")
+                    .long("max-mismatches")
+                    .help("Sets the largest number of mismatches between guide pairs to count (the minimum distance is always reported)")
+                    .takes_value(true)
+                    .default_value("2"))))
+        .subcommand(SubCommand::with_name("index")
+            .about("Builds a library index which can be passed to --library for fast startup")
+            .args(&library_args())
+            .arg(Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .help("Sets the output index file (*.sidx)")
+                .required(true)
+                .takes_value(true)))
+        .subcommand(SubCommand::with_name("merge")
+            .about("Joins count tables of separate runs on their sgRNA names")
+            .arg(Arg::with_name("COUNTS")
+                .short("c")
+                .long("counts")
+                .help("Sets the count tables (as written by screenr) to merge")
+                .required(true)
+                .min_values(2))
+            .arg(Arg::with_name("METADATA_COLUMNS")
+                .long("metadata-columns")
+                .help("Sets the annotation columns of the count tables (e.g. from --annotation-columns) which are not samples")
+                .min_values(1))
+            .arg(Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .help("Sets the output tsv to write the merged count table to (default = stdout)")
+                .takes_value(true)))
+        .subcommand(SubCommand::with_name("compare")
+            .about("Computes replicate-averaged log2 fold changes between reference and treatment samples of a count table")
+            .args(&comparison_args())
+            .arg(Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .help("Sets the output tsv to write fold changes to (default = stdout)")
+                .takes_value(true))
+            .arg(Arg::with_name("NB_TEST")
+                .long("nb-test")
+                .help("Tests each guide for depletion/enrichment with a negative binomial fit of the mean-variance relationship")))
+        .subcommand(SubCommand::with_name("rra")
+            .about("Ranks genes by robust rank aggregation of guide log2 fold changes between reference and treatment samples")
+            .args(&comparison_args())
+            .arg(Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .help("Sets the output tsv to write gene rankings to (default = stdout)")
+                .takes_value(true))
+            .arg(Arg::with_name("ALPHA")
+                .long("alpha")
+                .help("Sets the fraction of top-ranked guides considered by alpha-RRA")
+                .takes_value(true)
+                .default_value("0.25"))
+            .arg(Arg::with_name("PERMUTATIONS")
+                .long("permutations")
+                .help("Sets the number of random guide sets per gene used to derive p-values")
+                .takes_value(true)
+                .default_value("100"))
+            .arg(Arg::with_name("SEED")
+                .long("seed")
+                .help("Sets the random seed used for permutations")
+                .takes_value(true)
+                .default_value("42")))
+        .subcommand(SubCommand::with_name("phenotype")
+            .about("Computes growth (gamma), drug (tau) and relative (rho) phenotypes from a count table")
+            .arg(Arg::with_name("COUNTS")
+                .short("c")
+                .long("counts")
+                .help("Sets the count table (as written by screenr) to score")
+                .required(true)
+                .takes_value(true))
+            .arg(Arg::with_name("T0")
+                .long("t0")
+                .help("Sets the T0 sample name(s)")
+                .required(true)
+                .min_values(1))
+            .arg(Arg::with_name("UNTREATED")
+                .long("untreated")
+                .help("Sets the untreated endpoint sample name(s)")
+                .required(true)
+                .min_values(1))
+            .arg(Arg::with_name("TREATED")
+                .long("treated")
+                .help("Sets the treated endpoint sample name(s) [enables tau and rho]")
+                .min_values(1)
+                .requires("TREATED_DOUBLINGS"))
+            .arg(Arg::with_name("UNTREATED_DOUBLINGS")
+                .long("untreated-doublings")
+                .help("Sets the number of doublings of the untreated population between T0 and endpoint")
+                .required(true)
+                .takes_value(true))
+            .arg(Arg::with_name("TREATED_DOUBLINGS")
+                .long("treated-doublings")
+                .help("Sets the number of doublings of the treated population between T0 and endpoint")
+                .takes_value(true)
+                .requires("TREATED"))
+            .arg(Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .help("Sets the output tsv to write guide-level phenotypes to (default = stdout)")
+                .takes_value(true))
+            .arg(Arg::with_name("GENE_OUTPUT")
+                .long("gene-output")
+                .help("Sets the output tsv to write gene-level phenotypes and Mann-Whitney statistics against the controls to")
+                .takes_value(true))
+            .arg(Arg::with_name("TOP_GUIDES")
+                .long("top-guides")
+                .help("Sets the number of strongest guides per gene used for gene-level statistics")
+                .takes_value(true)
+                .validator(validate_positive)
+                .default_value("3"))
+            .arg(Arg::with_name("PSEUDOGENES")
+                .long("pseudogenes")
+                .help("Sets the number of control pseudogenes used to estimate the FDR (default = number of genes)")
+                .takes_value(true))
+            .arg(Arg::with_name("SEED")
+                .long("seed")
+                .help("Sets the random seed used to build pseudogenes")
+                .takes_value(true)
+                .default_value("42"))
+            .arg(Arg::with_name("NORMALIZE")
+                .long("normalize")
+                .help("Sets the method used to normalize samples before comparing them")
+                .takes_value(true)
+                .possible_values(&["cpm", "median-ratio", "control"])
+                .default_value("cpm"))
+            .arg(Arg::with_name("PSEUDOCOUNT")
+                .long("pseudocount")
+                .help("Sets the pseudocount added to every count before normalizing")
+                .takes_value(true)
+                .validator(validate_pseudocount)
+                .default_value("1")))
+        .subcommand(SubCommand::with_name("qc")
+            .about("Reports the concordance of log-normalized guide counts between samples of a count table")
+            .arg(Arg::with_name("COUNTS")
+                .short("c")
+                .long("counts")
+                .help("Sets the count table (as written by screenr) to compare samples of")
+                .required(true)
+                .takes_value(true))
+            .arg(Arg::with_name("SAMPLES")
+                .short("s")
+                .long("samples")
+                .help("Sets the sample names to correlate")
+                .required(true)
+                .min_values(2))
+            .arg(Arg::with_name("GROUPS")
+                .short("g")
+                .long("groups")
+                .help("Sets the replicate group of each sample (e.g. T0 T0 end end) to flag discordant replicates")
+                .min_values(2))
+            .arg(Arg::with_name("METHOD")
+                .short("m")
+                .long("method")
+                .help("Sets the correlation used for the matrix output and replicate flags")
+                .takes_value(true)
+                .possible_values(&["pearson", "spearman"])
+                .default_value("pearson"))
+            .arg(Arg::with_name("MIN_CORRELATION")
+                .long("min-correlation")
+                .help("Sets the mean correlation with the other replicates of a group below which a sample is flagged")
+                .takes_value(true)
+                .default_value("0.8"))
+            .arg(Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .help("Sets the output tsv to write the correlations of every sample pair to (default = stdout)")
+                .takes_value(true))
+            .arg(Arg::with_name("MATRIX")
+                .long("matrix")
+                .help("Sets the output tsv to write the sample correlation matrix to")
+                .takes_value(true))
+            .arg(Arg::with_name("REPORT")
+                .long("report")
+                .help("Sets the output html to write a self-contained QC report to")
+                .takes_value(true)))
+        .subcommand(SubCommand::with_name("validate")
+            .about("Strictly validates the record structure of fastq file(s)")
+            .arg(Arg::with_name("INPUT")
+                .short("i")
+                .long("input")
+                .help("Sets the input fastq(s) file to validate (*.fastq, *.fq, *.fastq.gz, *.fq.gz)")
+                .required(true)
+                .min_values(1)))
+}
+
+/// Selects a setting, preferring explicit arguments over registry settings over defaults
+fn resolve_setting(matches: &ArgMatches, name: &str, configured: Option<&String>) -> String {
+    match (matches.occurrences_of(name), configured) {
+        (0, Some(value)) => value.to_string(),
+        _ => matches.value_of(name)
+            .expect("ERROR: unable to load provided setting")
+            .to_string()
+    }
+}
+
+/// Builds the tabular library column layout from the arguments and registry settings
+fn build_table_spec(matches: &ArgMatches, config: Option<&LibraryConfig>) -> TableSpec {
+    let mut spec = config.map_or_else(TableSpec::default, |c| c.table_spec());
+    let explicit = |name: &str| match matches.occurrences_of(name) {
+        0 => None,
+        _ => matches.value_of(name).map(Column::parse)
+    };
+
+    if let Some(id) = explicit("ID_COLUMN") {
+        spec.id = id;
+    }
+    if let Some(seq) = explicit("SEQ_COLUMN") {
+        spec.seq = seq;
+    }
+    if let Some(gene) = explicit("GENE_COLUMN") {
+        spec.gene = Some(gene);
+    }
+    if let Some(values) = matches.values_of("ANNOTATION_COLUMNS") {
+        spec.annotations = values.map(Column::parse).collect();
+    }
+    if matches.is_present("HEADER") {
+        spec.header = Some(true);
+    } else if matches.is_present("NO_HEADER") {
+        spec.header = Some(false);
+    }
+    spec
+}
+
+/// Opens a fastq reader of either plain or gzipped fastq
+fn open_fastq(filename: &str) -> Box<dyn FastqRead> {
+    match assign_reader(filename) {
+        Some(ReaderType::FASTQ) => Box::new(
+            Fastq::new(filename).unwrap_or_else(|_| panic!("ERROR: Could not open '{}'", filename))
+        ),
+        Some(ReaderType::FASTQGZ) => Box::new(
+            FastqGz::new(filename).unwrap_or_else(|_| panic!("ERROR: Could not open '{}'", filename))
+        ),
+        _ => panic!("ERROR: '{}' is not a fastq file (*.fastq, *.fq, *.fastq.gz, *.fq.gz)", filename)
+    }
 }
 
 /// Performs the matching algorithm
-fn run_matching(input_sequences: &str, library: &mut Library, idx: usize) {
-    match assign_reader(input_sequences) {
-        Some(ReaderType::FASTQ) => {
-            let mut reader = Fastq::new(input_sequences).unwrap();
-            library.match_reader(&mut reader, idx);
-        },
-        Some(ReaderType::FASTQGZ) => {
-            let mut reader = FastqGz::new(input_sequences).unwrap();
-            library.match_reader(&mut reader, idx);
-        },
-        _ => {}
+fn run_matching(sample: &SampleEntry, library: &mut Library, idx: usize) {
+    let mut r1 = open_fastq(&sample.r1);
+    match sample.r2.as_ref() {
+        Some(r2) => library.match_paired_reader(&mut *r1, &mut *open_fastq(r2), idx),
+        None => library.match_reader(&mut *r1, idx)
+    }
+}
+
+/// Consumes a reader in strict mode and reports on its validity
+fn validate_reader<R: FastqRead>(filename: &str, reader: R) -> bool {
+    // records are read as bytes so that invalid UTF-8 is reported rather than fatal
+    let mut reader = reader;
+    while reader.next_record_ref().is_some() {}
+    let validation = reader.validation()
+        .expect("ERROR: reader was not created in strict mode");
+
+    println!(
+        "{}\t{} records\t{} invalid",
+        filename,
+        validation.num_records(),
+        validation.num_invalid()
+    );
+    if let Some(rec) = validation.first_invalid() {
+        println!(
+            "  first malformed record: #{} ({}): {}",
+            rec.index, rec.name, rec.reason
+        );
+    }
+    validation.is_valid()
+}
+
+/// Performs strict validation of all provided fastq files
+fn run_validation(matches: &ArgMatches) {
+    let input_sequences: Vec<&str> = matches.values_of("INPUT")
+        .expect("ERROR: unable to load provided input")
+        .collect();
+
+    let mut all_valid = true;
+    for input in input_sequences {
+        let is_valid = match assign_reader(input) {
+            Some(ReaderType::FASTQ) => {
+                let reader = Fastq::new(input)
+                    .expect("ERROR: unable to open fastq")
+                    .with_validation();
+                validate_reader(input, reader)
+            },
+            Some(ReaderType::FASTQGZ) => {
+                let reader = FastqGz::new(input)
+                    .expect("ERROR: unable to open fastq")
+                    .with_validation();
+                validate_reader(input, reader)
+            },
+            _ => panic!("ERROR: unrecognized fastq extension: {}", input)
+        };
+        all_valid &= is_valid;
+    }
+
+    if !all_valid {
+        std::process::exit(1);
+    }
+}
+
+/// Writes normalized counts and the size factors used to compute them
+fn run_normalization(matches: &ArgMatches, library: &Library, names: &[&str]) {
+    let method: NormMethod = matches.value_of("NORMALIZE")
+        .unwrap()
+        .parse()
+        .expect("ERROR: Could not parse normalization method");
+    let pseudocount: f64 = matches.value_of("PSEUDOCOUNT")
+        .unwrap()
+        .parse()
+        .expect("ERROR: pseudocount must be a number");
+    let output_filename = matches.value_of("NORMALIZED_OUTPUT").unwrap();
+    let size_factor_filename = format!(
+        "{}.size_factors.tsv",
+        output_filename.trim_end_matches(".tsv")
+    );
+
+    let table = CountTable::from_library(library, names);
+    let factors = size_factors(&table, method, pseudocount)
+        .expect("ERROR: Could not estimate size factors");
+    table.write_values(output_filename, &normalize(&table, &factors, pseudocount))
+        .expect("ERROR: Could not write normalized count table");
+    write_size_factors(&size_factor_filename, &table, &factors)
+        .expect("ERROR: Could not write size factors");
+}
+
+/// Exits quietly once stdout is closed early (e.g. piped to `head`) and fails on any other write error
+fn check_write(result: Result<(), Error>, message: &str) {
+    match result {
+        Ok(()) => {},
+        Err(e) if e.kind() == ErrorKind::BrokenPipe => std::process::exit(0),
+        Err(e) => panic!("{}: {}", message, e)
+    }
+}
+
+/// Opens the output file or falls back to stdout
+fn open_output(filename: Option<&str>) -> Box<dyn Write> {
+    match filename {
+        Some(filename) => Box::new(BufWriter::new(
+            File::create(filename).expect("ERROR: Could not create output file")
+        )),
+        None => Box::new(BufWriter::new(std::io::stdout()))
+    }
+}
+
+/// Reference and treatment samples of a normalized count table
+struct SampleGroups {
+    table: CountTable,
+    normalized: Vec<f64>,
+    reference: Vec<usize>,
+    treatment: Vec<usize>
+}
+impl SampleGroups {
+    fn compare(&self) -> Vec<GuideComparison> {
+        compare_groups(&self.table, &self.normalized, &self.reference, &self.treatment)
+    }
+}
+
+/// Reads and normalizes the reference and treatment samples of a count table
+fn load_sample_groups(matches: &ArgMatches) -> SampleGroups {
+    let reference: Vec<&str> = matches.values_of("REFERENCE").unwrap().collect();
+    let treatment: Vec<&str> = matches.values_of("TREATMENT").unwrap().collect();
+    let method: NormMethod = matches.value_of("NORMALIZE")
+        .unwrap()
+        .parse()
+        .expect("ERROR: Could not parse normalization method");
+    let pseudocount: f64 = matches.value_of("PSEUDOCOUNT")
+        .unwrap()
+        .parse()
+        .expect("ERROR: pseudocount must be a number");
+
+    let samples: Vec<&str> = reference.iter().chain(treatment.iter()).copied().collect();
+    let table = CountTable::read(
+        matches.value_of("COUNTS").unwrap(),
+        &samples,
+        &build_control_spec(matches)
+    ).expect("ERROR: Could not read count table");
+
+    let factors = size_factors(&table, method, pseudocount)
+        .expect("ERROR: Could not estimate size factors");
+    let normalized = normalize(&table, &factors, pseudocount);
+    SampleGroups {
+        table,
+        normalized,
+        reference: (0..reference.len()).collect(),
+        treatment: (reference.len()..samples.len()).collect()
+    }
+}
+
+/// Compares reference and treatment samples of a count table
+fn run_compare(matches: &ArgMatches) {
+    let groups = load_sample_groups(matches);
+    let mut writer = open_output(matches.value_of("OUTPUT"));
+    let written = if matches.is_present("NB_TEST") {
+        let (model, tests) = nb_test(&groups.table, &groups.normalized, &groups.reference, &groups.treatment);
+        eprintln!("Mean-variance fit:\tvariance = mean + {:.4} * mean^{:.4}", model.k, model.b);
+        write_nb_tests(&mut writer, &groups.table, &tests)
+    } else {
+        write_comparisons(&mut writer, &groups.table, &groups.compare())
+    };
+    check_write(
+        written.and_then(|_| writer.flush()),
+        "ERROR: Could not write fold changes"
+    );
+}
+
+/// Ranks genes by robust rank aggregation of their guides' fold changes
+fn run_rra(matches: &ArgMatches) {
+    let alpha = parse_f64(matches, "ALPHA");
+    let permutations: usize = matches.value_of("PERMUTATIONS")
+        .unwrap()
+        .parse()
+        .expect("ERROR: permutations must be a positive integer");
+    let mut rng = Rng::new(
+        matches.value_of("SEED").unwrap().parse().expect("ERROR: seed must be a positive integer")
+    );
+
+    let groups = load_sample_groups(matches);
+    let genes = rank_genes(&groups.table, &groups.compare(), alpha, permutations, &mut rng);
+    let mut writer = open_output(matches.value_of("OUTPUT"));
+    check_write(
+        write_rra(&mut writer, &genes)
+            .and_then(|_| writer.flush()),
+        "ERROR: Could not write gene rankings"
+    );
+}
+
+/// Reports the concordance of samples of a count table
+fn run_qc(matches: &ArgMatches) {
+    let samples: Vec<&str> = matches.values_of("SAMPLES").unwrap().collect();
+    let groups: Option<Vec<&str>> = matches.values_of("GROUPS").map(|x| x.collect());
+    if groups.as_ref().is_some_and(|g| g.len() != samples.len()) {
+        panic!("ERROR: number of groups must match the number of samples");
+    }
+    let method: CorrelationMethod = matches.value_of("METHOD")
+        .unwrap()
+        .parse()
+        .expect("ERROR: Could not parse correlation method");
+    let min_correlation = parse_f64(matches, "MIN_CORRELATION");
+
+    let table = CountTable::read(
+        matches.value_of("COUNTS").unwrap(),
+        &samples,
+        &build_control_spec(matches)
+    ).expect("ERROR: Could not read count table");
+    let concordance = Concordance::new(&table);
+
+    let mut writer = open_output(matches.value_of("OUTPUT"));
+    check_write(
+        concordance.write_pairs(&mut writer)
+            .and_then(|_| writer.flush()),
+        "ERROR: Could not write sample correlations"
+    );
+    if let Some(filename) = matches.value_of("MATRIX") {
+        let mut writer = open_output(Some(filename));
+        check_write(
+            concordance.write_matrix(&mut writer, method)
+                .and_then(|_| writer.flush()),
+            "ERROR: Could not write correlation matrix"
+        );
+    }
+    if let Some(filename) = matches.value_of("REPORT") {
+        let report = QcReport::new(&table);
+        let report = match groups.as_ref() {
+            Some(groups) => report.with_groups(groups),
+            None => report
+        };
+        report.save(filename)
+            .expect("ERROR: Could not write QC report");
+    }
+    if let Some(groups) = groups {
+        report_replicates(&concordance, &groups, method, min_correlation);
+    }
+}
+
+/// Prints the concordance of each sample with its replicates, flagging those below a threshold
+fn report_replicates(concordance: &Concordance, groups: &[&str], method: CorrelationMethod, min_correlation: f64) {
+    let replicates = concordance.replicate_concordance(groups, method);
+    eprintln!(">> Replicate Concordance ({} guides)", concordance.num_guides);
+    eprintln!("Sample\tGroup\tCorrelation\tStatus");
+    for ((sample, group), correlation) in concordance.samples.iter().zip(groups).zip(replicates) {
+        let status = if correlation.is_nan() {
+            "no replicates"
+        } else if correlation < min_correlation {
+            "LOW"
+        } else {
+            "ok"
+        };
+        eprintln!("{}\t{}\t{:.4}\t{}", sample, group, correlation, status);
+    }
+}
+
+/// Parses a floating point argument
+fn parse_f64(matches: &ArgMatches, name: &str) -> f64 {
+    matches.value_of(name)
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|_| panic!("ERROR: {} must be a number", name))
+}
+
+/// Scores Horlbeck-style growth, drug and relative phenotypes
+fn run_phenotype(matches: &ArgMatches) {
+    let t0: Vec<&str> = matches.values_of("T0").unwrap().collect();
+    let untreated: Vec<&str> = matches.values_of("UNTREATED").unwrap().collect();
+    let treated: Vec<&str> = matches.values_of("TREATED").map_or_else(Vec::new, |x| x.collect());
+    let method: NormMethod = matches.value_of("NORMALIZE")
+        .unwrap()
+        .parse()
+        .expect("ERROR: Could not parse normalization method");
+    let pseudocount = parse_f64(matches, "PSEUDOCOUNT");
+    let untreated_doublings = parse_f64(matches, "UNTREATED_DOUBLINGS");
+
+    let samples: Vec<&str> = t0.iter().chain(untreated.iter()).chain(treated.iter()).copied().collect();
+    let table = CountTable::read(
+        matches.value_of("COUNTS").unwrap(),
+        &samples,
+        &build_control_spec(matches)
+    ).expect("ERROR: Could not read count table");
+    let factors = size_factors(&table, method, pseudocount)
+        .expect("ERROR: Could not estimate size factors");
+    let normalized = normalize(&table, &factors, pseudocount);
+
+    let t0_idx: Vec<usize> = (0..t0.len()).collect();
+    let untreated_idx: Vec<usize> = (t0.len()..t0.len() + untreated.len()).collect();
+    let treated_idx: Vec<usize> = (t0.len() + untreated.len()..samples.len()).collect();
+    let score = |name: &str, reference: &[usize], treatment: &[usize], doublings: f64| {
+        let comparisons = compare_groups(&table, &normalized, reference, treatment);
+        Phenotype::from_comparisons(name, &table, &comparisons, doublings)
+            .expect("ERROR: Could not score phenotype")
     };
+
+    let mut phenotypes = vec![score("gamma", &t0_idx, &untreated_idx, untreated_doublings)];
+    if !treated.is_empty() {
+        let treated_doublings = parse_f64(matches, "TREATED_DOUBLINGS");
+        phenotypes.push(score("tau", &t0_idx, &treated_idx, treated_doublings));
+        phenotypes.push(score("rho", &untreated_idx, &treated_idx, untreated_doublings - treated_doublings));
+    }
+
+    let mut writer = open_output(matches.value_of("OUTPUT"));
+    check_write(
+        write_guide_phenotypes(&mut writer, &table, &phenotypes)
+            .and_then(|_| writer.flush()),
+        "ERROR: Could not write phenotypes"
+    );
+    if let Some(filename) = matches.value_of("GENE_OUTPUT") {
+        let top_n: usize = matches.value_of("TOP_GUIDES")
+            .unwrap()
+            .parse()
+            .expect("ERROR: top guides must be a positive integer");
+        let num_pseudogenes: Option<usize> = matches.value_of("PSEUDOGENES")
+            .map(|x| x.parse().expect("ERROR: pseudogenes must be a positive integer"));
+        let mut rng = Rng::new(
+            matches.value_of("SEED").unwrap().parse().expect("ERROR: seed must be a positive integer")
+        );
+        let stats: Vec<Vec<GeneStats>> = phenotypes
+            .iter()
+            .map(|p| {
+                gene_statistics(&table, p, top_n, num_pseudogenes, &mut rng)
+                    .expect("ERROR: Could not compute gene statistics")
+            })
+            .collect();
+        let mut writer = open_output(Some(filename));
+        check_write(
+            write_gene_stats(&mut writer, &phenotypes, &stats)
+                .and_then(|_| writer.flush()),
+            "ERROR: Could not write gene phenotypes"
+        );
+    }
 }
 
 /// Confirms that inputs are in the expected format
-fn validate_inputs(input_sequences: &Vec<&str>, library_filename: &str, names: &Vec<&str>, guide_sequence: &str) {
+fn validate_inputs(input_sequences: &[&str], names: &[&str]) {
    
     // validates `input_sequences` and `names` are equal lengths
     assert_eq!(
@@ -69,89 +795,355 @@ fn validate_inputs(input_sequences: &Vec<&str>, library_filename: &str, names: &
         names.len(),
         "Number of files + number of names provided are unequal"
     );
+}
+
+/// Reads the sample sheet or pairs the provided inputs with their names
+fn load_sample_sheet(matches: &ArgMatches) -> SampleSheet {
+    if let Some(filename) = matches.value_of("SAMPLE_SHEET") {
+        return SampleSheet::read(filename)
+            .expect("ERROR: Could not read sample sheet");
+    }
+    let input_sequences: Vec<&str> = matches.values_of("INPUT")
+        .expect("ERROR: unable to load provided input")
+        .collect();
+    let names: Option<Vec<&str>> = matches.values_of("NAMES").map(|x| x.collect());
+
+    // validate inputs
+    if let Some(names) = names.as_ref() {
+        validate_inputs(&input_sequences, names);
+    }
 
-    // validates `library_filename` exists
+    SampleSheet::from_inputs(&input_sequences, names.as_deref())
+        .expect("ERROR: Could not name samples")
+}
+
+/// Builds the control guide designation from the arguments
+fn build_control_spec(matches: &ArgMatches) -> ControlSpec {
+    let pattern = ControlSpec::from_pattern(matches.value_of("CONTROL_PATTERN").unwrap())
+        .expect("ERROR: Could not parse control pattern");
+    match matches.value_of("CONTROLS") {
+        Some(filename) => {
+            let spec = ControlSpec::from_list(filename)
+                .expect("ERROR: Could not read control list");
+            if matches.occurrences_of("CONTROL_PATTERN") > 0 {
+                spec.with_pattern_of(pattern)
+            } else {
+                spec
+            }
+        },
+        None => pattern
+    }
+}
+
+/// Builds the library subset filter from the arguments
+fn build_filter(matches: &ArgMatches) -> GuideFilter {
+    let mut filter = GuideFilter::default()
+        .report_off_panel(matches.is_present("OFF_PANEL"));
+    if let Some(filename) = matches.value_of("INCLUDE_GENES") {
+        filter = filter.include_genes(
+            read_name_list(filename).expect("ERROR: Could not read gene list")
+        );
+    }
+    if let Some(filename) = matches.value_of("EXCLUDE_GENES") {
+        filter = filter.exclude_genes(
+            read_name_list(filename).expect("ERROR: Could not read gene list")
+        );
+    }
+    if let Some(pattern) = matches.value_of("INCLUDE_PATTERN") {
+        filter = filter.pattern(pattern)
+            .expect("ERROR: Could not parse include pattern");
+    }
+    filter
+}
+
+/// Resolves and loads the library(s) described by the library arguments
+///
+/// Multiple libraries are loaded into a single `Library` with each guide
+/// tagged by the library it came from.
+fn load_library(matches: &ArgMatches, n_samples: usize) -> Library {
+    let library_names: Vec<&str> = matches.values_of("LIBRARY")
+        .expect("ERROR: unable to load provided library")
+        .collect();
+
+    // resolve libraries along with any registered settings
+    let registered: Vec<RegisteredLibrary> = library_names
+        .iter()
+        .map(|name| {
+            resolve_registered(name)
+                .expect("ERROR: Could not read library registry")
+                .unwrap_or_else(|| panic!("Provided library '{}' does not exist and is not a registered library", name))
+        })
+        .collect();
+
+    // guide and spacer settings are shared across all libraries
+    let guide_sequences: Vec<String> = registered
+        .iter()
+        .map(|r| resolve_setting(matches, "GUIDE", r.config.as_ref().and_then(|c| c.guide.as_ref())))
+        .collect();
+    assert!(
+        guide_sequences.iter().all(|g| g == &guide_sequences[0]),
+        "Libraries registered with different guide sequences cannot be combined"
+    );
+    let guide_sequence = &guide_sequences[0];
+    let spacer_lengths: Vec<usize> = registered
+        .iter()
+        .map(|r| {
+            let configured = r.config.as_ref().and_then(|c| c.spacer_length).map(|x| x.to_string());
+            resolve_setting(matches, "SPACER_LENGTH", configured.as_ref())
+                .parse()
+                .expect("ERROR: spacer length must be a positive integer")
+        })
+        .collect();
     assert!(
-        Path::new(library_filename).exists(),
-        "Provided library path does not exist"
+        spacer_lengths.iter().all(|x| *x == spacer_lengths[0]),
+        "Libraries registered with different spacer lengths cannot be combined"
     );
+    let spacer_len = spacer_lengths[0];
 
     // validates `guide_sequence` length is > 5
     assert!(
         guide_sequence.len() > 5,
         "Provided guide sequence must be at least 5 basepairs"
     );
+
+    let mut library = Library::new(guide_sequence, n_samples);
+    library.set_spacer_len(spacer_len);
+    library.set_control_spec(build_control_spec(matches));
+    library.set_filter(build_filter(matches));
+    for (name, registered) in library_names.iter().zip(registered.iter()) {
+        library.set_table_spec(build_table_spec(matches, registered.config.as_ref()));
+        library.load_source_as(name, &registered.source)
+            .expect("ERROR: Could not load library");
+    }
+    library
 }
 
-/// creates the known libraries 
-fn build_known_library() -> HashSet<String> {
+/// Serializes the loaded library(s) to an index file
+fn run_index(matches: &ArgMatches) {
+    let output_filename = matches.value_of("OUTPUT").unwrap();
+    assert!(
+        output_filename.ends_with(INDEX_EXTENSION),
+        "Index filename must end with {}", INDEX_EXTENSION
+    );
+    let library = load_library(matches, 0);
+    library.to_index()
+        .and_then(|index| index.save(output_filename))
+        .expect("ERROR: Could not write library index");
+    eprintln!("Indexed {} guides to {}", library.num_guides(), output_filename);
+}
 
-    // builds known libraries
-    let mut known_libraries = HashSet::new();
-    
-    known_libraries.insert(
-        "all".to_string(),
-        );
-    
-    for i in 1..8 {
-        let basename = format!("h{}", i);
-        known_libraries.insert(
-            basename.to_string(),
-            );
+/// Reports design statistics of a library
+fn run_library_check(matches: &ArgMatches) {
+    let max_mismatches: usize = matches.value_of("MAX_MISMATCHES")
+        .unwrap()
+        .parse()
+        .expect("ERROR: max mismatches must be a positive integer");
+    let library = load_library(matches, 0);
+    let stats = library.load_stats();
+    let close = library.close_guides(max_mismatches);
+
+    println!("records\t{}", stats.num_records);
+    println!("filtered\t{}", stats.num_filtered);
+    println!("guides\t{}", library.num_guides());
+    println!("genes\t{}", library.num_genes());
+    println!("controls\t{}", library.control_guides().len());
+    println!("duplicate_names\t{}", stats.num_duplicate_names);
+    println!("duplicate_sequences\t{}", stats.num_duplicate_seqs);
+    println!("missing_anchor\t{}", stats.num_missing_anchor);
+    println!("unpackable\t{}", stats.num_unpackable);
+    if library.num_sources() > 1 {
+        println!("cross_library_collisions\t{}", stats.num_cross_collisions);
+    }
+    match close.min_distance {
+        Some(dist) => println!("min_hamming_distance\t{}", dist),
+        None => println!("min_hamming_distance\tNA")
+    }
+    for (dist, n_pairs) in close.pairs.iter().enumerate() {
+        println!("pairs_{}_mismatch\t{}", dist, n_pairs);
     }
+    for (len, n_guides) in stats.spacer_lengths.iter() {
+        println!("length_{}\t{}", len, n_guides);
+    }
+    for (bin, n_guides) in stats.gc_bins.iter().enumerate() {
+        println!("gc_{}-{}%\t{}", bin * 10, (bin + 1) * 10, n_guides);
+    }
+}
 
-    known_libraries
+/// Prints a single row of the library listing
+fn print_library_row(name: &str, source: Option<LibrarySource>, config: Option<&LibraryConfig>, description: &str) {
+    match source {
+        Some(source) => {
+            let guide_sequence = config
+                .and_then(|c| c.guide.as_deref())
+                .unwrap_or("GTTTAAGAG");
+            let mut library = Library::new(guide_sequence, 0);
+            if let Some(config) = config {
+                library.set_table_spec(config.table_spec());
+            }
+            library.load_source(&source)
+                .expect("ERROR: Could not load library");
+            println!(
+                "{}\t{}\t{}\t{}",
+                name, library.num_guides(), source.describe(), description
+            );
+        },
+        None => {
+            println!("{}\tNA\tunavailable\t{}", name, description);
+        }
+    }
 }
 
-/// If provided library is known and supported will reassign shorthand to fixed path
-fn reassign_library(lib: &str) -> Option<String> {
-    let cargo_path = env!("CARGO_MANIFEST_DIR");
-    let known_libraries = build_known_library();
-    if known_libraries.contains(lib) {
-        Some(format!("{}/data/libraries/CRISPRi_v2_crop28.{}.fasta.gz", cargo_path, lib))
+/// Lists the registered libraries along with their sizes
+fn run_library_list() {
+    let user_libraries = load_user_libraries()
+        .expect("ERROR: Could not read library registry");
+
+    println!("Name\tGuides\tSource\tDescription");
+    for (name, config) in user_libraries.iter() {
+        print_library_row(
+            name,
+            resolve_library(&config.path),
+            Some(config),
+            config.description.as_deref().unwrap_or("")
+        );
     }
-    else {
-        None
+    for bundled in BUNDLED_LIBRARIES {
+        if !user_libraries.contains_key(bundled.name) {
+            print_library_row(bundled.name, resolve_library(bundled.name), None, bundled.description);
+        }
     }
 }
 
-fn main() {
-    let matches = get_args().get_matches();
-    
-    let input_sequences: Vec<&str> = matches.values_of("INPUT")
-        .expect("ERROR: unable to load provided input")
-        .collect();
-    let mut library_filename = matches.value_of("LIBRARY")
-        .expect("ERROR: unable to load provided library").to_string();
-    let output_filename = matches.value_of("OUTPUT");
-    let names: Vec<&str> = matches.values_of("NAMES")
-        .expect("ERROR: unable to load provided label")
-        .collect();
-    let guide_sequence = matches.value_of("GUIDE")
-        .expect("ERROR: unable to load provided guide");
-    
+/// Global options which take a value
+const GLOBAL_VALUE_OPTIONS: [&str; 2] = ["--control-pattern", "--controls"];
 
-    match reassign_library(&library_filename) {
-        Some(pathname) => {
-            library_filename = pathname;
-        },
-        None => {}
+/// Inserts the `count` subcommand into flat invocations from before subcommands existed
+///
+/// e.g. `screenr -i x.fastq.gz -n x -l h1` is treated as `screenr count -i x.fastq.gz -n x -l h1`.
+/// Only the first argument that is neither a global option nor its value decides:
+/// a count option means a flat invocation, anything else is left to the parser.
+fn legacy_args(mut args: Vec<String>) -> Vec<String> {
+    let mut idx = 1;
+    while let Some(arg) = args.get(idx) {
+        if GLOBAL_VALUE_OPTIONS.contains(&arg.as_str()) {
+            idx += 2;
+        } else if GLOBAL_VALUE_OPTIONS.iter().any(|opt| arg.starts_with(&format!("{}=", opt))) {
+            idx += 1;
+        } else if ["-h", "--help", "-V", "--version"].contains(&arg.as_str()) || !arg.starts_with('-') {
+            break;
+        } else {
+            args.insert(1, "count".to_string());
+            break;
+        }
+    }
+    args
+}
+
+/// Merges count tables of separate runs
+fn run_merge(matches: &ArgMatches) {
+    let mut filenames = matches.values_of("COUNTS").unwrap();
+    let metadata_columns: Vec<&str> = matches.values_of("METADATA_COLUMNS").map_or_else(Vec::new, |x| x.collect());
+    let mut merged = MergedTable::read(filenames.next().unwrap(), &metadata_columns)
+        .expect("ERROR: Could not read count table");
+    for filename in filenames {
+        let table = MergedTable::read(filename, &metadata_columns)
+            .expect("ERROR: Could not read count table");
+        merged.merge(table)
+            .expect("ERROR: Could not merge count tables");
+    }
+    let mut writer = open_output(matches.value_of("OUTPUT"));
+    check_write(
+        merged.write(&mut writer)
+            .and_then(|_| writer.flush()),
+        "ERROR: Could not write merged count table"
+    );
+}
+
+/// Output of the sample metadata and read statistics
+///
+/// Sample sheets have their metadata written next to the count table or else
+/// the normalized table unless a destination is given. Counts written to
+/// stdout have nothing to place it beside, so it is skipped with a warning.
+fn sample_output(matches: &ArgMatches) -> Option<String> {
+    if let Some(filename) = matches.value_of("SAMPLE_OUTPUT") {
+        return Some(filename.to_string());
+    }
+    matches.value_of("SAMPLE_SHEET")?;
+    let table_filename = matches.value_of("OUTPUT")
+        .or_else(|| matches.value_of("NORMALIZED_OUTPUT"));
+    let table_filename = match table_filename {
+        Some(filename) => filename,
+        None => {
+            eprintln!("WARNING: sample metadata is not written when counts go to stdout (set --sample-output to keep it)");
+            return None;
+        }
     };
+    Some(format!("{}.samples.tsv", table_filename.trim_end_matches(".tsv")))
+}
 
-    // validate inputs
-    validate_inputs(&input_sequences, &library_filename, &names, guide_sequence);
+/// Counts guides of every input and writes the count table and any requested reports
+fn run_count(matches: &ArgMatches) {
+    let sheet = load_sample_sheet(matches);
+    let output_filename = matches.value_of("OUTPUT");
+    let sample_filename = sample_output(matches);
+    let names = sheet.names();
 
     // load library
-    let mut library = Library::new(guide_sequence, input_sequences.len());
-    library.load_library(&library_filename).expect("ERROR: Could not load library");
+    let mut library = load_library(matches, names.len());
 
-    // iterate sequences
-    for idx in 0..input_sequences.len() {
+    // iterate samples
+    for (idx, sample) in sheet.samples.iter().enumerate() {
         run_matching(
-            input_sequences[idx], 
+            sample, 
             &mut library,
             idx);
     }
+    let mapping: Vec<SampleStats> = (0..names.len())
+        .map(|idx| *library.sample_stats(idx))
+        .collect();
+
+    // write sample metadata alongside the count table
+    if let Some(sample_filename) = sample_filename {
+        sheet.write_summary(&sample_filename, &mapping)
+            .expect("ERROR: Could not write sample summary");
+    }
+
+    // write normalized output
+    if matches.is_present("NORMALIZE") {
+        run_normalization(matches, &library, &names);
+    }
+
+    // write replicate concordance
+    if let Some(filename) = matches.value_of("QC_OUTPUT") {
+        let concordance = Concordance::new(&CountTable::from_library(&library, &names));
+        let mut writer = open_output(Some(filename));
+        check_write(
+            concordance.write_pairs(&mut writer)
+                .and_then(|_| writer.flush()),
+            "ERROR: Could not write sample correlations"
+        );
+    }
+
+    // write multiqc statistics
+    if let Some(filename) = matches.value_of("MULTIQC") {
+        if !filename.ends_with(MULTIQC_SUFFIX) {
+            eprintln!("WARNING: MultiQC only picks up custom content files ending in '{}'", MULTIQC_SUFFIX);
+        }
+        library.write_multiqc(filename, &sheet)
+            .expect("ERROR: Could not write MultiQC statistics");
+    }
+
+    // write html report
+    if let Some(filename) = matches.value_of("REPORT") {
+        let table = CountTable::from_library(&library, &names);
+        let report = QcReport::new(&table).with_mapping(&mapping);
+        let report = match matches.value_of("SAMPLE_SHEET") {
+            Some(_) => report.with_sheet(&sheet),
+            None => report
+        };
+        report.save(filename)
+            .expect("ERROR: Could not write QC report");
+    }
 
     // write output
     match output_filename {
@@ -160,8 +1152,57 @@ fn main() {
                 .expect("ERROR: Could not write count table");
         },
         None => {
-            library.print_count_table(names);
+            check_write(library.print_count_table(names), "ERROR: Could not write count table");
         }
     }
 }
 
+fn main() {
+    let matches = get_args().get_matches_from(legacy_args(std::env::args().collect()));
+
+    match matches.subcommand() {
+        ("count", Some(sub_matches)) => run_count(sub_matches),
+        ("index", Some(sub_matches)) => run_index(sub_matches),
+        ("merge", Some(sub_matches)) => run_merge(sub_matches),
+        ("compare", Some(sub_matches)) => run_compare(sub_matches),
+        ("rra", Some(sub_matches)) => run_rra(sub_matches),
+        ("phenotype", Some(sub_matches)) => run_phenotype(sub_matches),
+        ("qc", Some(sub_matches)) => run_qc(sub_matches),
+        ("validate", Some(sub_matches)) => run_validation(sub_matches),
+        ("library", Some(sub_matches)) => {
+            match sub_matches.subcommand() {
+                ("list", Some(_)) => run_library_list(),
+                ("check", Some(check_matches)) => run_library_check(check_matches),
+                _ => {}
+            }
+        },
+        _ => {}
+    }
+}
+
+#[test]
+fn test_legacy_args() {
+    let args = |x: &str| x.split(' ').map(|a| a.to_string()).collect::<Vec<_>>();
+
+    // flat invocations gain the count subcommand, even with sample names matching subcommands
+    assert_eq!(legacy_args(args("screenr -i x.fq -n x -l h1")), args("screenr count -i x.fq -n x -l h1"));
+    assert_eq!(legacy_args(args("screenr -i x.fq -n index -l h1")), args("screenr count -i x.fq -n index -l h1"));
+    assert_eq!(legacy_args(args("screenr -i merge.fq -n merge -l h1")), args("screenr count -i merge.fq -n merge -l h1"));
+    assert_eq!(
+        legacy_args(args("screenr --controls qc -i x.fq -l h1")),
+        args("screenr count --controls qc -i x.fq -l h1")
+    );
+
+    // subcommands, help and global options followed by a subcommand are untouched
+    for line in [
+        "screenr",
+        "screenr --help",
+        "screenr -V",
+        "screenr count -i x.fq -l h1",
+        "screenr qc -c counts.tsv -s a b",
+        "screenr --control-pattern ^safe compare -c counts.tsv",
+        "screenr --controls=count.txt index -l h1 -o h1.sidx"
+    ] {
+        assert_eq!(legacy_args(args(line)), args(line));
+    }
+}
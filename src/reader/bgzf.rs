@@ -0,0 +1,315 @@
+use std::{fs::File, io::{Cursor, Error, ErrorKind, Read}, sync::{Arc, Mutex, mpsc::{Receiver, SyncSender, sync_channel}}, thread};
+use flate2::{Crc, read::{DeflateDecoder, MultiGzDecoder}};
+
+/// Size of the fixed portion of a gzip member header
+const HEADER_LEN: usize = 12;
+
+/// Size of the CRC32 + ISIZE trailer of a gzip member
+const TRAILER_LEN: usize = 8;
+
+/// Number of blocks queued ahead of each worker
+const BLOCKS_PER_THREAD: usize = 16;
+
+/// Size of the chunks decompressed from gzip members lacking a BGZF block size
+const STREAM_CHUNK_LEN: u64 = 1 << 16;
+
+/// Gzip header flags of the optional fields
+const FHCRC: u8 = 2;
+const FEXTRA: u8 = 4;
+const FNAME: u8 = 8;
+const FCOMMENT: u8 = 16;
+
+/// Extracts the BGZF block size from a gzip member's extra field
+fn parse_block_size(extra: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    while pos + 4 <= extra.len() {
+        let slen = u16::from_le_bytes([extra[pos + 2], extra[pos + 3]]) as usize;
+        if extra[pos] == b'B' && extra[pos + 1] == b'C' && slen == 2 && pos + 6 <= extra.len() {
+            let bsize = u16::from_le_bytes([extra[pos + 4], extra[pos + 5]]) as usize;
+            return Some(bsize + 1);
+        }
+        pos += 4 + slen;
+    }
+    None
+}
+
+/// Whether the fixed header begins a deflate compressed gzip member
+fn is_gzip(header: &[u8]) -> bool {
+    header[0] == 31 && header[1] == 139 && header[2] == 8
+}
+
+/// Returns the length of the extra field of a fixed gzip header (if it has one)
+fn parse_extra_len(header: &[u8]) -> Option<usize> {
+    if is_gzip(header) && header[3] & FEXTRA != 0 {
+        Some(u16::from_le_bytes([header[10], header[11]]) as usize)
+    } else {
+        None
+    }
+}
+
+/// Returns the offset of a gzip member's compressed data, skipping its optional header fields
+fn parse_header(member: &[u8]) -> Option<usize> {
+    let flags = *member.get(3)?;
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        pos += 2 + u16::from_le_bytes([*member.get(10)?, *member.get(11)?]) as usize;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            pos += memchr::memchr(0, member.get(pos..)?)? + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    (pos <= member.len()).then_some(pos)
+}
+
+/// Determines whether a file is BGZF (blocked gzip) by inspecting its first header
+pub fn is_bgzf(filename: &str) -> Result<bool, Error> {
+    let mut file = File::open(filename)?;
+    let mut header = [0u8; HEADER_LEN];
+    if file.read_exact(&mut header).is_err() {
+        return Ok(false);
+    }
+    let xlen = match parse_extra_len(&header) {
+        Some(xlen) => xlen,
+        None => return Ok(false)
+    };
+    let mut extra = vec![0u8; xlen];
+    if file.read_exact(&mut extra).is_err() {
+        return Ok(false);
+    }
+    Ok(parse_block_size(&extra).is_some())
+}
+
+/// Decompresses a single raw BGZF block and validates its trailer
+fn inflate_block(block: &[u8]) -> Result<Vec<u8>, Error> {
+    let start = parse_header(block)
+        .filter(|start| start + TRAILER_LEN <= block.len())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "truncated BGZF block header"))?;
+    let trailer = &block[block.len() - TRAILER_LEN..];
+    let expected_crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let expected_len = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]) as usize;
+
+    let cdata = &block[start .. block.len() - TRAILER_LEN];
+    let mut output = Vec::with_capacity(expected_len);
+    DeflateDecoder::new(cdata).read_to_end(&mut output)?;
+
+    let mut crc = Crc::new();
+    crc.update(&output);
+    if output.len() != expected_len || crc.sum() != expected_crc {
+        return Err(Error::new(ErrorKind::InvalidData, "BGZF block failed integrity check"));
+    }
+    Ok(output)
+}
+
+/// A gzip member read from a BGZF stream
+enum Member {
+    /// Raw BGZF block (header through trailer)
+    Block(Vec<u8>),
+    /// Header bytes already read of a member lacking a BGZF block size
+    Plain(Vec<u8>)
+}
+
+/// Reads the next gzip member of a stream
+fn read_member<R: Read>(inner: &mut R) -> Result<Option<Member>, Error> {
+    let mut header = [0u8; HEADER_LEN];
+    let mut filled = 0;
+    while filled < HEADER_LEN {
+        let len = inner.read(&mut header[filled..])?;
+        if len == 0 {
+            break;
+        }
+        filled += len;
+    }
+    if filled == 0 {
+        return Ok(None);
+    }
+    if filled < HEADER_LEN || !is_gzip(&header) {
+        return Err(Error::new(ErrorKind::InvalidData, "invalid gzip member in BGZF stream"));
+    }
+
+    let xlen = match parse_extra_len(&header) {
+        Some(xlen) => xlen,
+        None => return Ok(Some(Member::Plain(header.to_vec())))
+    };
+    let mut extra = vec![0u8; xlen];
+    inner.read_exact(&mut extra)?;
+    let block_size = match parse_block_size(&extra) {
+        Some(block_size) => block_size,
+        None => return Ok(Some(Member::Plain([&header[..], &extra].concat())))
+    };
+    if block_size < HEADER_LEN + xlen + TRAILER_LEN {
+        return Err(Error::new(ErrorKind::InvalidData, "invalid BGZF block size"));
+    }
+
+    let mut block = Vec::with_capacity(block_size);
+    block.extend_from_slice(&header);
+    block.extend_from_slice(&extra);
+    block.resize(block_size, 0);
+    inner.read_exact(&mut block[HEADER_LEN + xlen..])?;
+    Ok(Some(Member::Block(block)))
+}
+
+/// Decompressed output of a block, delivered once a worker is done with it
+type Inflated = Receiver<Result<Vec<u8>, Error>>;
+
+/// A raw block and where to send its decompressed output
+type Job = (Vec<u8>, SyncSender<Result<Vec<u8>, Error>>);
+
+/// Queues output that is already decompressed
+fn send_ready(order: &SyncSender<Inflated>, output: Result<Vec<u8>, Error>) -> bool {
+    let (tx, rx) = sync_channel(1);
+    tx.send(output).is_ok() && order.send(rx).is_ok()
+}
+
+/// Reads blocks off the stream and queues them for the workers in their original order
+///
+/// Members lacking a BGZF block size (e.g. ordinary gzip concatenated to
+/// a BGZF file) are decompressed here with the rest of the stream.
+fn dispatch<R: Read>(mut inner: R, jobs: SyncSender<Job>, order: SyncSender<Inflated>) {
+    loop {
+        match read_member(&mut inner) {
+            Ok(Some(Member::Block(block))) => {
+                let (tx, rx) = sync_channel(1);
+                if jobs.send((block, tx)).is_err() || order.send(rx).is_err() {
+                    return;
+                }
+            },
+            Ok(Some(Member::Plain(header))) => {
+                let mut decoder = MultiGzDecoder::new(Cursor::new(header).chain(inner));
+                loop {
+                    let mut chunk = Vec::new();
+                    match decoder.by_ref().take(STREAM_CHUNK_LEN).read_to_end(&mut chunk) {
+                        Ok(0) => return,
+                        Ok(_) => if !send_ready(&order, Ok(chunk)) {
+                            return;
+                        },
+                        Err(e) => {
+                            send_ready(&order, Err(e));
+                            return;
+                        }
+                    }
+                }
+            },
+            Ok(None) => return,
+            Err(e) => {
+                send_ready(&order, Err(e));
+                return;
+            }
+        }
+    }
+}
+
+/// Decompresses blocks until the job queue is closed
+fn inflate_jobs(jobs: Arc<Mutex<Receiver<Job>>>) {
+    loop {
+        let job = jobs.lock().expect("BGZF job queue poisoned").recv();
+        match job {
+            Ok((block, tx)) => {
+                // the decoder may have been dropped, leaving no one to receive the block
+                let _ = tx.send(inflate_block(&block));
+            },
+            Err(_) => return
+        }
+    }
+}
+
+/// Decoder for BGZF (blocked gzip) streams which inflates blocks in parallel
+///
+/// A reader thread reads raw blocks into a bounded queue drained by a
+/// persistent pool of worker threads, so reading overlaps decompression,
+/// and decompressed blocks are handed out in their original order.
+#[derive(Debug)]
+pub struct BgzfDecoder {
+    blocks: Receiver<Inflated>,
+    output: Vec<u8>,
+    pos: usize,
+    eof: bool
+}
+impl BgzfDecoder {
+
+    /// Creates a decoder using the provided number of worker threads
+    pub fn new<R: Read + Send + 'static>(inner: R, threads: usize) -> Self {
+        let threads = threads.max(1);
+        let (jobs_tx, jobs_rx) = sync_channel(threads * BLOCKS_PER_THREAD);
+        let (order_tx, order_rx) = sync_channel(threads * BLOCKS_PER_THREAD);
+        let jobs_rx = Arc::new(Mutex::new(jobs_rx));
+        for _ in 0..threads {
+            let jobs_rx = Arc::clone(&jobs_rx);
+            thread::spawn(move || inflate_jobs(jobs_rx));
+        }
+        thread::spawn(move || dispatch(inner, jobs_tx, order_tx));
+        Self {
+            blocks: order_rx,
+            output: Vec::new(),
+            pos: 0,
+            eof: false
+        }
+    }
+
+    /// Waits for the next block in order
+    fn fill_output(&mut self) -> Result<(), Error> {
+        let inflated = match self.blocks.recv() {
+            Ok(inflated) => inflated,
+            Err(_) => {
+                self.eof = true;
+                return Ok(());
+            }
+        };
+        self.output = inflated.recv()
+            .map_err(|_| Error::other("BGZF worker thread panicked"))??;
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl Read for BgzfDecoder {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        // empty blocks (e.g. the EOF marker) decompress to nothing
+        while self.pos == self.output.len() && !self.eof {
+            self.fill_output()?;
+        }
+        let len = buf.len().min(self.output.len() - self.pos);
+        buf[..len].copy_from_slice(&self.output[self.pos .. self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// Gzip decoder selecting parallel BGZF decompression when possible
+#[derive(Debug)]
+pub enum GzipDecoder {
+    Stream(MultiGzDecoder<File>),
+    Blocked(BgzfDecoder)
+}
+impl GzipDecoder {
+
+    /// Opens a gzip file using all available cores for BGZF inputs
+    pub fn open(filename: &str) -> Result<Self, Error> {
+        let threads = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        Self::open_with_threads(filename, threads)
+    }
+
+    /// Opens a gzip file falling back to the streaming decoder for ordinary gzip
+    pub fn open_with_threads(filename: &str, threads: usize) -> Result<Self, Error> {
+        let file = File::open(filename)?;
+        if is_bgzf(filename)? {
+            Ok(Self::Blocked(BgzfDecoder::new(file, threads)))
+        } else {
+            Ok(Self::Stream(MultiGzDecoder::new(file)))
+        }
+    }
+}
+
+impl Read for GzipDecoder {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        match self {
+            Self::Stream(decoder) => decoder.read(buf),
+            Self::Blocked(decoder) => decoder.read(buf)
+        }
+    }
+}
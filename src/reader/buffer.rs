@@ -0,0 +1,56 @@
+use std::{io::{BufRead, Error}, ops::Range};
+use super::FastqRecordRef;
+
+/// Reusable byte buffer holding the lines of a single fastq record
+///
+/// Lines are appended into one allocation which is cleared (but not freed)
+/// between records, so iterating a file does not allocate per read.
+#[derive(Debug, Default)]
+pub struct RecordBuffer {
+    buffer: Vec<u8>,
+    lines: [Range<usize>; 4]
+}
+impl RecordBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the next four lines and returns the number of lines read
+    pub fn fill<B: BufRead>(&mut self, reader: &mut B) -> Result<usize, Error> {
+        self.buffer.clear();
+        let mut n_lines = 0;
+        for i in 0..4 {
+            let start = self.buffer.len();
+            let len = reader.read_until(b'\n', &mut self.buffer)?;
+            if len == 0 {
+                self.lines[i] = start..start;
+                continue;
+            }
+            n_lines += 1;
+            self.lines[i] = Self::trimmed(&self.buffer, start);
+        }
+        Ok(n_lines)
+    }
+
+    /// Range of the line beginning at `start` without surrounding whitespace
+    fn trimmed(buffer: &[u8], start: usize) -> Range<usize> {
+        let line = &buffer[start..];
+        let trimmed = line.trim_ascii();
+        let offset = trimmed.as_ptr() as usize - line.as_ptr() as usize;
+        start + offset .. start + offset + trimmed.len()
+    }
+
+    /// Whether the separator line begins with a `+`
+    pub fn separator_ok(&self) -> bool {
+        self.buffer[self.lines[2].clone()].starts_with(b"+")
+    }
+
+    /// Borrowed view of the currently buffered record
+    pub fn record(&self) -> FastqRecordRef<'_> {
+        FastqRecordRef::new(
+            &self.buffer[self.lines[0].clone()],
+            &self.buffer[self.lines[1].clone()],
+            &self.buffer[self.lines[3].clone()]
+        )
+    }
+}
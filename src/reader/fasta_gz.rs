@@ -1,15 +1,15 @@
-use std::{fs::File, io::{BufRead, BufReader, Error}};
+use std::{fs::File, io::{BufRead, BufReader, Error, Read}};
 use flate2::read::MultiGzDecoder;
 
 use super::{FastaRead, FastaRecord};
 
 #[derive(Debug)]
-pub struct FastaGz {
-    bufreader: BufReader<MultiGzDecoder<File>>,
+pub struct FastaGz<R: Read = File> {
+    bufreader: BufReader<MultiGzDecoder<R>>,
     line: String
 }
 
-impl FastaRead for FastaGz {
+impl<R: Read> FastaRead for FastaGz<R> {
 
     fn pop_line(&mut self) -> Result<bool, Error> {
         self.line.clear();
@@ -39,7 +39,7 @@ impl FastaRead for FastaGz {
 
 }
 
-impl Iterator for FastaGz {
+impl<R: Read> Iterator for FastaGz<R> {
 
    type Item = FastaRecord;
 
@@ -54,14 +54,21 @@ impl FastaGz {
     /// Creates a new buffer for a provided file
     pub fn new(filename: &str) -> Result<Self, Error> {
         let file = File::open(filename)?;
-        let gzip_conv = MultiGzDecoder::new(file);
+        Ok(Self::from_reader(file))
+    }
+}
+
+impl<R: Read> FastaGz<R> {
+
+    /// Creates a new buffer over any gzipped reader (e.g. embedded bytes)
+    pub fn from_reader(reader: R) -> Self {
+        let gzip_conv = MultiGzDecoder::new(reader);
         let bufreader = BufReader::new(gzip_conv);
         let line = String::new();
-        let fqr = Self {
+        Self {
             bufreader,
             line
-        };
-        Ok(fqr)
+        }
     }
 }
 
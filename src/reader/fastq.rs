@@ -1,40 +1,41 @@
 use std::{fs::File, io::{BufReader, BufRead, Error}};
-use super::{FastqRead, FastqRecord};
+use super::{FastqRead, FastqRecord, FastqRecordRef, FastqValidation, RecordBuffer};
 
 pub struct Fastq {
    bufreader: BufReader<File>,
-   line: String
+   line: String,
+   record_buffer: RecordBuffer,
+   validation: Option<FastqValidation>
 }
 
 impl FastqRead for Fastq {
 
     fn pop_line(&mut self) -> Result<bool, Error> {
         self.line.clear();
-        let len = self.bufreader.read_line(&mut self.line)
-            .expect("IO error in reading fastq");
+        let len = self.bufreader.read_line(&mut self.line)?;
         Ok(len > 0)
     }
 
     fn next_record(&mut self) -> Option<FastqRecord> {
-        let mut rec = FastqRecord::new();
-
-        for i in 0..4 {
-            if self.pop_line().expect("Unexpected end to file") {
-                match i {
-                    0 => rec.add_name(self.line.trim()),
-                    1 => rec.add_seq(self.line.trim()),
-                    3 => rec.add_qual(self.line.trim()),
-                    _ => {}
-                };
-            }
-        }
-        if !rec.is_empty(){
+        self.next_record_ref().map(|rec| rec.to_record())
+    }
+
+    fn next_record_ref(&mut self) -> Option<FastqRecordRef<'_>> {
+        let n_lines = self.record_buffer.fill(&mut self.bufreader)
+            .expect("IO error in reading fastq");
+        let rec = self.record_buffer.record();
+        let separator_ok = self.record_buffer.separator_ok();
+        if FastqValidation::admit(self.validation.as_mut(), &rec, separator_ok, n_lines) {
             Some(rec)
         } else {
             None
         }
     }
 
+    fn validation(&self) -> Option<&FastqValidation> {
+        self.validation.as_ref()
+    }
+
 }
 
 impl Iterator for Fastq {
@@ -56,10 +57,18 @@ impl Fastq {
         let line = String::new();
         let fqr = Self {
             bufreader,
-            line
+            line,
+            record_buffer: RecordBuffer::new(),
+            validation: None
         };
         Ok(fqr)
     }
+
+    /// Enables strict validation of the record structure
+    pub fn with_validation(mut self) -> Self {
+        self.validation = Some(FastqValidation::new());
+        self
+    }
 }
 
 
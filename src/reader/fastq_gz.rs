@@ -1,43 +1,43 @@
-use std::{fs::File, io::{BufRead, BufReader, Error}};
-use flate2::read::MultiGzDecoder;
+use std::io::{BufRead, BufReader, Error};
 
-use super::{FastqRead, FastqRecord};
+use super::{GzipDecoder, FastqRead, FastqRecord, FastqRecordRef, FastqValidation, RecordBuffer};
 
 #[derive(Debug)]
 pub struct FastqGz {
-    bufreader: BufReader<MultiGzDecoder<File>>,
-    line: String
+    bufreader: BufReader<GzipDecoder>,
+    line: String,
+    record_buffer: RecordBuffer,
+    validation: Option<FastqValidation>
 }
 
 impl FastqRead for FastqGz {
 
     fn pop_line(&mut self) -> Result<bool, Error> {
         self.line.clear();
-        let len = self.bufreader.read_line(&mut self.line)
-            .expect("IO error in reading fastq");
+        let len = self.bufreader.read_line(&mut self.line)?;
         Ok(len > 0)
     }
 
     fn next_record(&mut self) -> Option<FastqRecord> {
-        let mut rec = FastqRecord::new();
-
-        for i in 0..4 {
-            if self.pop_line().expect("Unexpected end to file") {
-                match i {
-                    0 => rec.add_name(self.line.trim()),
-                    1 => rec.add_seq(self.line.trim()),
-                    3 => rec.add_qual(self.line.trim()),
-                    _ => {}
-                };
-            }
-        }
-        if !rec.is_empty(){
+        self.next_record_ref().map(|rec| rec.to_record())
+    }
+
+    fn next_record_ref(&mut self) -> Option<FastqRecordRef<'_>> {
+        let n_lines = self.record_buffer.fill(&mut self.bufreader)
+            .expect("IO error in reading fastq");
+        let rec = self.record_buffer.record();
+        let separator_ok = self.record_buffer.separator_ok();
+        if FastqValidation::admit(self.validation.as_mut(), &rec, separator_ok, n_lines) {
             Some(rec)
         } else {
             None
         }
     }
 
+    fn validation(&self) -> Option<&FastqValidation> {
+        self.validation.as_ref()
+    }
+
 }
 
 impl Iterator for FastqGz {
@@ -53,16 +53,34 @@ impl Iterator for FastqGz {
 impl FastqGz {
 
     /// Creates a new buffer for a provided file
+    ///
+    /// BGZF inputs are decompressed in parallel on all available cores
     pub fn new(filename: &str) -> Result<Self, Error> {
-        let file = File::open(filename)?;
-        let gzip_conv = MultiGzDecoder::new(file);
+        let gzip_conv = GzipDecoder::open(filename)?;
+        Ok(Self::from_decoder(gzip_conv))
+    }
+
+    /// Creates a new buffer using a fixed number of BGZF decompression threads
+    pub fn with_threads(filename: &str, threads: usize) -> Result<Self, Error> {
+        let gzip_conv = GzipDecoder::open_with_threads(filename, threads)?;
+        Ok(Self::from_decoder(gzip_conv))
+    }
+
+    fn from_decoder(gzip_conv: GzipDecoder) -> Self {
         let bufreader = BufReader::new(gzip_conv);
         let line = String::new();
-        let fqr = Self {
+        Self {
             bufreader,
-            line
-        };
-        Ok(fqr)
+            line,
+            record_buffer: RecordBuffer::new(),
+            validation: None
+        }
+    }
+
+    /// Enables strict validation of the record structure
+    pub fn with_validation(mut self) -> Self {
+        self.validation = Some(FastqValidation::new());
+        self
     }
 }
 
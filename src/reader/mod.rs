@@ -1,13 +1,20 @@
+pub mod bgzf;
+pub mod buffer;
 pub mod fastq;
 pub mod fasta;
 pub mod fasta_gz;
 pub mod fastq_gz;
+#[allow(clippy::module_inception)]
 pub mod reader;
 pub mod record;
+pub mod validate;
 
 pub use fastq::Fastq;
 pub use fasta::Fasta;
 pub use fasta_gz::FastaGz;
 pub use fastq_gz::FastqGz;
-pub use record::{FastqRecord, FastaRecord};
+pub use bgzf::{BgzfDecoder, GzipDecoder, is_bgzf};
+pub use buffer::RecordBuffer;
+pub use record::{FastqRecord, FastqRecordRef, FastaRecord};
 pub use reader::{FastqRead, FastaRead};
+pub use validate::{FastqValidation, InvalidRecord};
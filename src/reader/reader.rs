@@ -1,6 +1,6 @@
 use std::io::Error;
 use super::{
-    FastqRecord, FastaRecord
+    FastqRecord, FastqRecordRef, FastaRecord, FastqValidation
 };
 
 pub trait FastaRead {
@@ -17,4 +17,10 @@ pub trait FastqRead {
 
     fn next_record(&mut self) -> Option<FastqRecord>;
 
+    /// Returns a borrowed view of the next record without allocating
+    fn next_record_ref(&mut self) -> Option<FastqRecordRef<'_>>;
+
+    /// Returns the validation state if strict validation is enabled
+    fn validation(&self) -> Option<&FastqValidation>;
+
 }
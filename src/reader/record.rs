@@ -3,6 +3,11 @@ pub struct FastaRecord {
     name: String,
     seq: String
 }
+impl Default for FastaRecord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl FastaRecord {
     pub fn new() -> Self {
         Self {
@@ -33,6 +38,11 @@ pub struct FastqRecord {
     seq: String,
     qual: String
 }
+impl Default for FastqRecord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl FastqRecord {
     pub fn new() -> Self {
         Self {
@@ -66,6 +76,49 @@ impl FastqRecord {
     pub fn get_qual(&self) -> &str {
         &self.qual
     }
+    /// Borrows the record as a byte-slice view
+    pub fn as_record_ref(&self) -> FastqRecordRef<'_> {
+        FastqRecordRef::new(
+            self.name.as_bytes(),
+            self.seq.as_bytes(),
+            self.qual.as_bytes()
+        )
+    }
+}
+
+/// A borrowed view of a fastq record, typically backed by a reader's internal buffer
+#[derive(Debug, Clone, Copy)]
+pub struct FastqRecordRef<'a> {
+    name: &'a [u8],
+    seq: &'a [u8],
+    qual: &'a [u8]
+}
+impl<'a> FastqRecordRef<'a> {
+    pub fn new(name: &'a [u8], seq: &'a [u8], qual: &'a [u8]) -> Self {
+        Self { name, seq, qual }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.name.is_empty()
+            | self.seq.is_empty()
+            | self.qual.is_empty()
+    }
+    pub fn get_name(&self) -> &'a [u8] {
+        self.name
+    }
+    pub fn get_seq(&self) -> &'a [u8] {
+        self.seq
+    }
+    pub fn get_qual(&self) -> &'a [u8] {
+        self.qual
+    }
+    /// Copies the record into an owned record, replacing invalid UTF-8
+    pub fn to_record(&self) -> FastqRecord {
+        let mut rec = FastqRecord::new();
+        rec.add_name(&String::from_utf8_lossy(self.name));
+        rec.add_seq(&String::from_utf8_lossy(self.seq));
+        rec.add_qual(&String::from_utf8_lossy(self.qual));
+        rec
+    }
 }
 
 #[test]
@@ -102,4 +155,10 @@ fn test_record() {
     assert_eq!(rec.get_name(), name);
     assert_eq!(rec.get_seq(), seq);
     assert_eq!(rec.get_qual(), qual);
+
+    let rec_ref = rec.as_record_ref();
+    assert!(!rec_ref.is_empty(), "Failed empty test");
+    assert_eq!(rec_ref.get_name(), name.as_bytes());
+    assert_eq!(rec_ref.get_seq(), seq.as_bytes());
+    assert_eq!(rec_ref.get_qual(), qual.as_bytes());
 }
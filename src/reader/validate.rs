@@ -0,0 +1,105 @@
+use super::FastqRecordRef;
+
+/// Describes the first structurally invalid record observed
+#[derive(Debug, Clone)]
+pub struct InvalidRecord {
+    pub index: usize,
+    pub name: String,
+    pub reason: String
+}
+
+/// Tracks the structural validity of the records passed through a FASTQ reader
+#[derive(Debug, Default, Clone)]
+pub struct FastqValidation {
+    n_records: usize,
+    n_invalid: usize,
+    first_invalid: Option<InvalidRecord>
+}
+impl FastqValidation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Determines why a record is malformed (if it is)
+    fn diagnose(rec: &FastqRecordRef, separator_ok: bool, complete: bool) -> Option<String> {
+        if !complete {
+            Some("record is truncated".to_string())
+        }
+        else if [rec.get_name(), rec.get_seq(), rec.get_qual()].iter().any(|x| std::str::from_utf8(x).is_err()) {
+            Some("record contains invalid UTF-8".to_string())
+        }
+        else if !rec.get_name().starts_with(b"@") {
+            Some("header line does not begin with '@'".to_string())
+        }
+        else if !separator_ok {
+            Some("separator line does not begin with '+'".to_string())
+        }
+        else if rec.get_seq().len() != rec.get_qual().len() {
+            Some(format!(
+                "sequence length ({}) does not match quality length ({})",
+                rec.get_seq().len(),
+                rec.get_qual().len()
+            ))
+        }
+        else {
+            None
+        }
+    }
+
+    /// Validates a single record and updates the running counts
+    pub fn inspect(&mut self, rec: &FastqRecordRef, separator_ok: bool, complete: bool) -> bool {
+        self.n_records += 1;
+        match Self::diagnose(rec, separator_ok, complete) {
+            Some(reason) => {
+                self.n_invalid += 1;
+                if self.first_invalid.is_none() {
+                    self.first_invalid = Some(InvalidRecord {
+                        index: self.n_records,
+                        name: String::from_utf8_lossy(rec.get_name()).to_string(),
+                        reason
+                    });
+                }
+                false
+            },
+            None => true
+        }
+    }
+
+    /// Decides whether a reader yields the record it just read, validating it in strict mode
+    ///
+    /// Blank lines at the end of a file mark its end rather than a truncated record and
+    /// strict mode keeps iterating past complete records with empty fields.
+    pub fn admit(validation: Option<&mut Self>, rec: &FastqRecordRef, separator_ok: bool, n_lines: usize) -> bool {
+        let complete = n_lines == 4;
+        let blank = [rec.get_name(), rec.get_seq(), rec.get_qual()].iter().all(|x| x.is_empty()) && !separator_ok;
+        if n_lines == 0 || (!complete && blank) {
+            return false;
+        }
+        match validation {
+            Some(validation) => {
+                validation.inspect(rec, separator_ok, complete);
+                complete || !rec.is_empty()
+            },
+            None => !rec.is_empty()
+        }
+    }
+
+    /// Number of records inspected
+    pub fn num_records(&self) -> usize {
+        self.n_records
+    }
+
+    /// Number of records found to be malformed
+    pub fn num_invalid(&self) -> usize {
+        self.n_invalid
+    }
+
+    /// The first malformed record (1-based index) if any were observed
+    pub fn first_invalid(&self) -> Option<&InvalidRecord> {
+        self.first_invalid.as_ref()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.n_invalid == 0
+    }
+}
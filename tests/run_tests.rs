@@ -2,9 +2,13 @@
 
 use screenr::reader::{
     Fasta, FastaGz,
-    Fastq, FastqGz
+    Fastq, FastqGz,
+    BgzfDecoder, FastqRead, is_bgzf
 };
-use screenr::crispr::Library;
+use std::io::{Cursor, Read, Write};
+use flate2::{Compression, Crc, GzBuilder, read::MultiGzDecoder, write::DeflateEncoder};
+use screenr::crispr::{Column, ControlSpec, GuideFilter, Library, LibraryIndex, TableSpec, resolve_library};
+use screenr::analysis::{Concordance, CorrelationMethod, CountTable, MergedTable, NormMethod, Phenotype, QcReport, compare_groups, normalize, size_factors};
 
 
 #[test]
@@ -59,6 +63,92 @@ fn test_fastq_gz() {
     assert_eq!(n_rec, 2500);
 }
 
+/// Builds a BGZF block whose header carries a file name, comment and header CRC
+fn bgzf_block(data: &[u8]) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    let cdata = encoder.finish().unwrap();
+    let mut crc = Crc::new();
+    crc.update(data);
+
+    let mut block = vec![31, 139, 8, 2 | 4 | 8 | 16, 0, 0, 0, 0, 0, 255, 6, 0, b'B', b'C', 2, 0, 0, 0];
+    block.extend_from_slice(b"reads.fastq\0a comment\0");
+    let bsize = (block.len() + 2 + cdata.len() + 8 - 1) as u16;
+    block[16..18].copy_from_slice(&bsize.to_le_bytes());
+    let mut header_crc = Crc::new();
+    header_crc.update(&block);
+    block.extend_from_slice(&(header_crc.sum() as u16).to_le_bytes());
+    block.extend_from_slice(&cdata);
+    block.extend_from_slice(&crc.sum().to_le_bytes());
+    block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    block
+}
+
+#[test]
+fn test_bgzf_header_fields() {
+    let mut stream = bgzf_block(b"@a\nACGT\n+\nIIII\n");
+    stream.extend(bgzf_block(b"@b\nTTTT\n+\nIIII\n"));
+    stream.extend(bgzf_block(b""));
+
+    for threads in [1, 4] {
+        let mut output = String::new();
+        BgzfDecoder::new(Cursor::new(stream.clone()), threads)
+            .read_to_string(&mut output)
+            .expect("Unable to decode BGZF stream");
+        assert_eq!(output, "@a\nACGT\n+\nIIII\n@b\nTTTT\n+\nIIII\n");
+    }
+}
+
+#[test]
+fn test_bgzf_plain_members() {
+    // ordinary gzip members appended to a BGZF file are decompressed in sequence
+    let mut stream = std::fs::read("data/test/test.bgzf.fastq.gz").unwrap();
+    let mut expected = Vec::new();
+    MultiGzDecoder::new(&stream[..]).read_to_end(&mut expected).unwrap();
+    for (i, name) in ["extra.fastq", "more.fastq"].iter().enumerate() {
+        let record = format!("@extra{}\nACGT\n+\nIIII\n", i);
+        let mut encoder = GzBuilder::new()
+            .filename(*name)
+            .write(Vec::new(), Compression::default());
+        encoder.write_all(record.as_bytes()).unwrap();
+        stream.extend(encoder.finish().unwrap());
+        expected.extend_from_slice(record.as_bytes());
+    }
+    stream.extend(bgzf_block(b"@last\nACGT\n+\nIIII\n"));
+    expected.extend_from_slice(b"@last\nACGT\n+\nIIII\n");
+
+    let mut output = Vec::new();
+    BgzfDecoder::new(Cursor::new(stream), 4)
+        .read_to_end(&mut output)
+        .expect("Unable to decode mixed gzip stream");
+    assert_eq!(output, expected);
+
+    // input that is not gzip at all is still an error
+    let mut output = Vec::new();
+    assert!(BgzfDecoder::new(Cursor::new(b"not a gzip file".to_vec()), 2).read_to_end(&mut output).is_err());
+}
+
+#[test]
+fn test_fastq_bgzf() {
+    assert!(is_bgzf("data/test/test.bgzf.fastq.gz").expect("Unable to open file"));
+    assert!(!is_bgzf("data/test/test.fastq.gz").expect("Unable to open file"));
+
+    for threads in [1, 4] {
+        let mut reader = FastqGz::with_threads("data/test/test.bgzf.fastq.gz", threads)
+            .expect("Unable to open reader");
+        let mut expected = Fastq::new("data/test/test.fastq")
+            .expect("Unable to open reader");
+
+        let mut n_rec = 0;
+        while let Some(rec) = reader.next_record_ref() {
+            let exp = expected.next().expect("Readers out of sync");
+            assert_eq!(rec.get_seq(), exp.get_seq().as_bytes());
+            n_rec += 1;
+        }
+        assert_eq!(n_rec, 2500);
+    }
+}
+
 #[test]
 fn test_library() {
     let guide_seq = "GTTTAAGAG"; 
@@ -68,7 +158,7 @@ fn test_library() {
         .expect("File reader not able to be created");
     assert!(lib_created);
 
-    lib.print_count_table(vec!["lib1"]);
+    lib.print_count_table(vec!["lib1"]).unwrap();
 }
 
 #[test]
@@ -97,3 +187,589 @@ fn test_multi_sample() {
         library.match_reader(&mut reader, i);
     }
 }
+
+#[test]
+fn test_fastq_validation() {
+    let mut reader = FastqGz::new("data/test/test.fastq.gz")
+        .expect("Unable to open reader")
+        .with_validation();
+    reader.by_ref().for_each(drop);
+
+    let validation = reader.validation().expect("Validation not enabled");
+    assert_eq!(validation.num_records(), 2500);
+    assert!(validation.is_valid());
+}
+
+#[test]
+fn test_fastq_validation_malformed() {
+    let mut reader = Fastq::new("data/test/malformed.fastq")
+        .expect("Unable to open reader")
+        .with_validation();
+    reader.by_ref().for_each(drop);
+
+    let validation = reader.validation().expect("Validation not enabled");
+    assert_eq!(validation.num_records(), 4);
+    assert_eq!(validation.num_invalid(), 3);
+
+    let first = validation.first_invalid().expect("No invalid record found");
+    assert_eq!(first.index, 2);
+    assert_eq!(first.name, "b");
+}
+
+#[test]
+fn test_fastq_validation_invalid_utf8() {
+    let filename = std::env::temp_dir().join(format!("screenr_utf8_{}.fastq", std::process::id()));
+    std::fs::write(&filename, b"@a\nACGT\n+\nIIII\n@b\nACGT\n+\nII\xffI\n").unwrap();
+    let mut reader = Fastq::new(filename.to_str().unwrap())
+        .expect("Unable to open reader")
+        .with_validation();
+    while reader.next_record_ref().is_some() {}
+    std::fs::remove_file(&filename).unwrap();
+
+    let validation = reader.validation().expect("Validation not enabled");
+    assert_eq!(validation.num_records(), 2);
+    assert_eq!(validation.num_invalid(), 1);
+    let first = validation.first_invalid().expect("No invalid record found");
+    assert_eq!(first.name, "@b");
+    assert_eq!(first.reason, "record contains invalid UTF-8");
+
+    // owned records are read without panicking on the invalid bytes
+    std::fs::write(&filename, b"@a\nACGT\n+\nIIII\n@b\nACGT\n+\nII\xffI\n").unwrap();
+    let mut reader = Fastq::new(filename.to_str().unwrap())
+        .expect("Unable to open reader")
+        .with_validation();
+    assert_eq!(reader.by_ref().count(), 2);
+    std::fs::remove_file(&filename).unwrap();
+    assert_eq!(reader.validation().unwrap().num_invalid(), 1);
+}
+
+#[test]
+fn test_fastq_validation_trailing_blank_line() {
+    let filename = std::env::temp_dir().join(format!("screenr_blank_{}.fastq", std::process::id()));
+    std::fs::write(&filename, b"@a\nACGT\n+\nIIII\n@b\nACGT\n+\nIIII\n\n").unwrap();
+    let mut owned = Fastq::new(filename.to_str().unwrap())
+        .expect("Unable to open reader")
+        .with_validation();
+    let mut borrowed = Fastq::new(filename.to_str().unwrap())
+        .expect("Unable to open reader")
+        .with_validation();
+    assert_eq!(owned.by_ref().count(), 2);
+    while borrowed.next_record_ref().is_some() {}
+    std::fs::remove_file(&filename).unwrap();
+
+    for reader in [&owned, &borrowed] {
+        let validation = reader.validation().expect("Validation not enabled");
+        assert_eq!(validation.num_records(), 2);
+        assert!(validation.is_valid());
+    }
+}
+
+#[test]
+fn test_fastq_gz_record_ref() {
+    let mut owned = FastqGz::new("data/test/test.fastq.gz")
+        .expect("Unable to open reader");
+    let mut borrowed = FastqGz::new("data/test/test.fastq.gz")
+        .expect("Unable to open reader");
+
+    let mut n_rec = 0;
+    while let Some(rec) = borrowed.next_record_ref() {
+        let expected = owned.next().expect("Readers out of sync");
+        assert_eq!(rec.get_name(), expected.get_name().as_bytes());
+        assert_eq!(rec.get_seq(), expected.get_seq().as_bytes());
+        assert_eq!(rec.get_qual(), expected.get_qual().as_bytes());
+        n_rec += 1;
+    }
+
+    assert!(owned.next().is_none());
+    assert_eq!(n_rec, 2500);
+}
+
+#[test]
+fn test_library_guide_index() {
+    let guide_seq = "GTTTAAGAG"; 
+    let mut reader = FastqGz::new("data/test/test.fastq.gz")
+        .expect("unable to create fastqgz reader");
+    let mut library = Library::new(guide_seq, 2);
+    library.load_library("data/test/library.fasta")
+        .expect("unable to load library")
+        .expect("unable to load library reader");
+    assert_eq!(library.num_guides(), 1924);
+    assert_eq!(library.get_name(0), "AADACL2_+_151451732.23-P1P2_h1_Top5");
+    assert_eq!(library.get_gene(0), "AADACL2");
+
+    library.match_reader(&mut reader, 1);
+    let (first, second) = (0..library.num_guides())
+        .map(|g| library.get_counts(g))
+        .fold((0, 0), |acc, c| (acc.0 + c[0], acc.1 + c[1]));
+    assert_eq!(first, 0);
+    assert_eq!(second, 2017);
+}
+
+#[test]
+fn test_tabular_library() {
+    let guide_seq = "GTTTAAGAG"; 
+    let mut reader = FastqGz::new("data/test/test.fastq.gz")
+        .expect("unable to create fastqgz reader");
+    let mut library = Library::new(guide_seq, 1);
+    library.set_table_spec(TableSpec {
+        id: Column::parse("sgRNA_id"),
+        seq: Column::parse("sequence"),
+        gene: Some(Column::parse("gene")),
+        annotations: vec![Column::parse("4")],
+        header: None
+    });
+    library.load_library("data/test/library.csv")
+        .expect("unable to load library")
+        .expect("unable to load library reader");
+    assert_eq!(library.num_guides(), 1924);
+    assert_eq!(library.get_gene(0), "AADACL2");
+    assert_eq!(library.get_annotations(0), vec!["h1_Top5"]);
+
+    library.match_reader(&mut reader, 0);
+    let total: u32 = (0..library.num_guides())
+        .map(|g| library.get_counts(g)[0])
+        .sum();
+    assert_eq!(total, 2017);
+}
+
+#[test]
+fn test_registry_resolution() {
+    let source = resolve_library("h1")
+        .expect("Bundled library not resolved");
+    let mut library = Library::new("GTTTAAGAG", 1);
+    library.load_source(&source)
+        .expect("unable to load library")
+        .expect("unable to load library reader");
+    assert_eq!(library.num_guides(), 26050);
+
+    assert!(resolve_library("data/test/test.fasta").is_some());
+    assert!(resolve_library("not_a_library").is_none());
+}
+
+#[test]
+fn test_library_check() {
+    let mut library = Library::new("GTTTAAGAG", 0);
+    library.load_library("data/test/library.fasta")
+        .expect("unable to load library")
+        .expect("unable to load library reader");
+    let stats = library.load_stats();
+    assert_eq!(stats.num_records, 1924);
+    assert_eq!(stats.num_duplicate_seqs, 0);
+    assert_eq!(stats.num_missing_anchor, 0);
+    assert_eq!(stats.spacer_lengths.get(&19), Some(&1924));
+    assert_eq!(stats.gc_bins.iter().sum::<usize>(), 1924);
+    assert_eq!(library.close_guides(2).pairs, vec![0, 0, 0]);
+    assert_eq!(library.close_guides(2).min_distance, Some(3));
+    assert_eq!(library.num_mismatched_spacers(), 0);
+
+    // guides of another spacer length can never be matched
+    library.set_spacer_len(20);
+    assert_eq!(library.num_mismatched_spacers(), 1924);
+}
+
+#[test]
+fn test_multiple_libraries() {
+    let guide_seq = "GTTTAAGAG"; 
+    let mut reader = FastqGz::new("data/test/test.fastq.gz")
+        .expect("unable to create fastqgz reader");
+    let mut library = Library::new(guide_seq, 1);
+    library.load_source_as("fasta", &resolve_library("data/test/library.fasta").unwrap())
+        .expect("unable to load library")
+        .expect("unable to load library reader");
+    library.set_table_spec(TableSpec {
+        id: Column::parse("sgRNA_id"),
+        seq: Column::parse("sequence"),
+        gene: Some(Column::parse("gene")),
+        annotations: Vec::new(),
+        header: None
+    });
+    library.load_source_as("table", &resolve_library("data/test/library.csv").unwrap())
+        .expect("unable to load library")
+        .expect("unable to load library reader");
+
+    // identical guides from both libraries keep separate rows
+    assert_eq!(library.num_sources(), 2);
+    assert_eq!(library.num_guides(), 3848);
+    assert_eq!(library.get_source(0), Some("fasta"));
+    assert_eq!(library.get_source(1924), Some("table"));
+    assert_eq!(library.load_stats().num_cross_collisions, 1924);
+    assert_eq!(library.close_guides(1).min_distance, Some(0));
+
+    // colliding sequences are counted against the first library
+    library.match_reader(&mut reader, 0);
+    let total = |source: &str| -> u32 {
+        (0..library.num_guides())
+            .filter(|g| library.get_source(*g) == Some(source))
+            .map(|g| library.get_counts(g)[0])
+            .sum()
+    };
+    assert_eq!(total("fasta"), 2017);
+    assert_eq!(total("table"), 0);
+}
+
+#[test]
+fn test_control_guides() {
+    let guide_seq = "GTTTAAGAG"; 
+    let mut reader = FastqGz::new("data/test/test.fastq.gz")
+        .expect("unable to create fastqgz reader");
+    let mut library = Library::new(guide_seq, 1);
+    library.load_library("data/test/library.fasta")
+        .expect("unable to load library")
+        .expect("unable to load library reader");
+    let controls = library.control_guides();
+    assert_eq!(controls.len(), 140);
+    assert!(controls.iter().all(|g| library.get_gene(*g) == "non-targeting"));
+
+    library.match_reader(&mut reader, 0);
+    let control_reads: u32 = controls.iter()
+        .map(|g| library.get_counts(*g)[0])
+        .sum();
+    assert_eq!(control_reads, 44);
+}
+
+#[test]
+fn test_library_index() {
+    let guide_seq = "GTTTAAGAG"; 
+    let tmp_dir = std::env::temp_dir().join(format!("screenr_index_{}", std::process::id()));
+    std::fs::create_dir_all(&tmp_dir).unwrap();
+    let fasta = tmp_dir.join("library.fasta");
+    let index = tmp_dir.join("library.sidx");
+    std::fs::copy("data/test/library.fasta", &fasta).unwrap();
+
+    let mut library = Library::new(guide_seq, 0);
+    library.load_source_as("test", &resolve_library(fasta.to_str().unwrap()).unwrap())
+        .expect("unable to load library")
+        .expect("unable to load library reader");
+    library.to_index()
+        .and_then(|x| x.save(index.to_str().unwrap()))
+        .expect("unable to write index");
+
+    // libraries loaded from an index count identically
+    let mut reader = FastqGz::new("data/test/test.fastq.gz")
+        .expect("unable to create fastqgz reader");
+    let mut indexed = Library::new(guide_seq, 1);
+    indexed.load_library(index.to_str().unwrap())
+        .expect("unable to load index")
+        .expect("unable to load index reader");
+    assert_eq!(indexed.num_guides(), 1924);
+    assert_eq!(indexed.get_source(0), Some("test"));
+    assert_eq!(indexed.control_guides().len(), 140);
+    indexed.match_reader(&mut reader, 0);
+    let total: u32 = (0..indexed.num_guides())
+        .map(|g| indexed.get_counts(g)[0])
+        .sum();
+    assert_eq!(total, 2017);
+
+    // indexes only match reads of the spacer length they were built for
+    let mut mismatched = Library::new(guide_seq, 1);
+    mismatched.set_spacer_len(20);
+    assert!(mismatched.load_library(index.to_str().unwrap()).is_err());
+
+    // indexes are rejected once their source library changes
+    std::fs::write(&fasta, ">a_b\nACGTACGTACGTACGTACGGTTTAAGAG\n").unwrap();
+    assert!(LibraryIndex::open(index.to_str().unwrap()).is_err());
+    std::fs::remove_dir_all(&tmp_dir).unwrap();
+}
+
+#[test]
+fn test_library_filter() {
+    let guide_seq = "GTTTAAGAG"; 
+    let mut reader = FastqGz::new("data/test/test.fastq.gz")
+        .expect("unable to create fastqgz reader");
+    let mut library = Library::new(guide_seq, 1);
+    library.set_filter(
+        GuideFilter::default()
+            .exclude_genes(vec!["non-targeting".to_string()].into_iter().collect())
+            .report_off_panel(true)
+    );
+    library.load_library("data/test/library.fasta")
+        .expect("unable to load library")
+        .expect("unable to load library reader");
+    assert_eq!(library.num_guides(), 1784);
+    assert_eq!(library.load_stats().num_filtered, 140);
+    assert!(library.control_guides().is_empty());
+
+    library.match_reader(&mut reader, 0);
+    let total: u32 = (0..library.num_guides())
+        .map(|g| library.get_counts(g)[0])
+        .sum();
+    assert_eq!(total, 2017 - 44);
+
+    // reads of the excluded non-targeting guides are reported as off-panel
+    assert_eq!(library.sample_stats(0).num_off_panel, 44);
+    assert_eq!(library.sample_stats(0).num_assigned, 2017 - 44);
+
+    let mut library = Library::new(guide_seq, 1);
+    library.set_filter(GuideFilter::default().pattern("^AADACL2_").unwrap());
+    library.load_library("data/test/library.fasta")
+        .expect("unable to load library")
+        .expect("unable to load library reader");
+    assert!(library.num_guides() > 0);
+    assert!((0..library.num_guides()).all(|g| library.get_gene(g) == "AADACL2"));
+}
+
+#[test]
+fn test_normalization() {
+    let guide_seq = "GTTTAAGAG"; 
+    let mut reader = FastqGz::new("data/test/test.fastq.gz")
+        .expect("unable to create fastqgz reader");
+    let mut library = Library::new(guide_seq, 1);
+    library.load_library("data/test/library.fasta")
+        .expect("unable to load library")
+        .expect("unable to load library reader");
+    library.match_reader(&mut reader, 0);
+
+    let table = CountTable::from_library(&library, &["test"]);
+    assert_eq!(table.num_guides(), 1924);
+    assert_eq!(table.controls.iter().filter(|x| **x).count(), 140);
+
+    let factors = size_factors(&table, NormMethod::Cpm, 0.0).unwrap();
+    assert!((factors[0] - 2017.0 / 1e6).abs() < 1e-12);
+    let cpm = normalize(&table, &factors, 0.0);
+    assert!((cpm.iter().sum::<f64>() - 1e6).abs() < 1e-6);
+
+    // a single sample is its own reference
+    let factors = size_factors(&table, NormMethod::Control, 0.5).unwrap();
+    assert!((factors[0] - 1.0).abs() < 1e-12);
+}
+
+#[test]
+fn test_compare_samples() {
+    let guide_seq = "GTTTAAGAG"; 
+    let mut library = Library::new(guide_seq, 2);
+    library.load_library("data/test/library.fasta")
+        .expect("unable to load library")
+        .expect("unable to load library reader");
+    for idx in 0..2 {
+        let mut reader = FastqGz::new("data/test/test.fastq.gz")
+            .expect("unable to create fastqgz reader");
+        library.match_reader(&mut reader, idx);
+    }
+    let filename = std::env::temp_dir().join(format!("screenr_compare_{}.tsv", std::process::id()));
+    let filename = filename.to_str().unwrap();
+    library.write_count_table(filename, vec!["t0", "end"])
+        .expect("unable to write count table");
+
+    let table = CountTable::read(filename, &["t0", "end"], &ControlSpec::default())
+        .expect("unable to read count table");
+    std::fs::remove_file(filename).unwrap();
+    assert_eq!(table.num_guides(), 1924);
+    assert_eq!(table.row(0), &[1.0, 1.0]);
+    assert_eq!(table.controls.iter().filter(|x| **x).count(), 140);
+
+    // identical samples have no fold change
+    let factors = size_factors(&table, NormMethod::MedianRatio, 1.0).unwrap();
+    let normalized = normalize(&table, &factors, 1.0);
+    let comparisons = compare_groups(&table, &normalized, &[0], &[1]);
+    assert!(comparisons.iter().all(|c| c.log2fc.abs() < 1e-12));
+}
+
+#[test]
+fn test_phenotype_scores() {
+    let filename = std::env::temp_dir().join(format!("screenr_phenotype_{}.tsv", std::process::id()));
+    let filename = filename.to_str().unwrap();
+    std::fs::write(filename, "\
+sgRNA\tGene\tT0\tEnd
+A_1\tA\t100\t25
+A_2\tA\t100\t50
+B_1\tB\t100\t400
+non-targeting_1\tnon-targeting\t100\t100
+non-targeting_2\tnon-targeting\t100\t100
+").unwrap();
+    let table = CountTable::read(filename, &["T0", "End"], &ControlSpec::default())
+        .expect("unable to read count table");
+    std::fs::remove_file(filename).unwrap();
+
+    let factors = size_factors(&table, NormMethod::Control, 0.0).unwrap();
+    let normalized = normalize(&table, &factors, 0.0);
+    let comparisons = compare_groups(&table, &normalized, &[0], &[1]);
+    let gamma = Phenotype::from_comparisons("gamma", &table, &comparisons, 2.0).unwrap();
+    let expected = [-1.0, -0.5, 1.0, 0.0, 0.0];
+    assert!(gamma.scores.iter().zip(expected.iter()).all(|(x, y)| (x - y).abs() < 1e-9));
+
+    let genes = gamma.gene_scores(&table, 3);
+    assert_eq!((genes[0].0.as_str(), genes[0].1), ("A", 2));
+    assert!((genes[0].2 + 0.75).abs() < 1e-9);
+    assert_eq!((genes[1].0.as_str(), genes[1].1), ("B", 1));
+}
+
+#[test]
+fn test_replicate_concordance() {
+    let guide_seq = "GTTTAAGAG"; 
+    let mut library = Library::new(guide_seq, 2);
+    library.load_library("data/test/library.fasta")
+        .expect("unable to load library")
+        .expect("unable to load library reader");
+    for idx in 0..2 {
+        let mut reader = FastqGz::new("data/test/test.fastq.gz")
+            .expect("unable to create fastqgz reader");
+        library.match_reader(&mut reader, idx);
+    }
+
+    // identical samples are perfectly concordant
+    let concordance = Concordance::new(&CountTable::from_library(&library, &["r1", "r2"]));
+    assert!(concordance.num_guides > 0);
+    assert!((concordance.pearson[1] - 1.0).abs() < 1e-12);
+    assert!((concordance.spearman[1] - 1.0).abs() < 1e-12);
+    let replicates = concordance.replicate_concordance(&["a", "a"], CorrelationMethod::Pearson);
+    assert!(replicates.iter().all(|x| (x - 1.0).abs() < 1e-12));
+
+    let mut matrix = Vec::new();
+    concordance.write_matrix(&mut matrix, CorrelationMethod::Spearman).unwrap();
+    assert_eq!(
+        String::from_utf8(matrix).unwrap(),
+        "Sample\tr1\tr2\nr1\t1.000000\t1.000000\nr2\t1.000000\t1.000000\n"
+    );
+}
+
+#[test]
+fn test_sample_stats() {
+    let guide_seq = "GTTTAAGAG"; 
+    let mut library = Library::new(guide_seq, 2);
+    library.load_library("data/test/library.fasta")
+        .expect("unable to load library")
+        .expect("unable to load library reader");
+    let mut reader = FastqGz::new("data/test/test.fastq.gz")
+        .expect("unable to create fastqgz reader");
+    library.match_reader(&mut reader, 1);
+
+    // statistics are kept per sample once a reader is consumed
+    assert_eq!(library.sample_stats(0).num_total, 0);
+    let stats = library.sample_stats(1);
+    assert_eq!(stats.num_assigned, 2017);
+    assert_eq!(stats.num_control, 44);
+    assert!(stats.num_matches() >= stats.num_assigned);
+    assert!(stats.mapping_rate() > 0.0 && stats.mapping_rate() <= 1.0);
+
+    let table = CountTable::from_library(&library, &["empty", "test"]);
+    let mut html = Vec::new();
+    QcReport::new(&table)
+        .with_mapping(&[*library.sample_stats(0), *library.sample_stats(1)])
+        .write(&mut html)
+        .expect("unable to write report");
+    assert!(String::from_utf8(html).unwrap().contains("<td>2017</td>"));
+}
+
+#[test]
+fn test_merge_tables() {
+    let guide_seq = "GTTTAAGAG"; 
+    let dir = std::env::temp_dir();
+    let filenames: Vec<String> = ["a", "b"].iter()
+        .map(|x| dir.join(format!("screenr_merge_{}_{}.tsv", x, std::process::id())).to_str().unwrap().to_string())
+        .collect();
+    for (idx, pattern) in ["", "^AADACL2_"].iter().enumerate() {
+        let mut library = Library::new(guide_seq, 1);
+        library.set_filter(GuideFilter::default().pattern(pattern).unwrap());
+        library.load_library("data/test/library.fasta")
+            .expect("unable to load library")
+            .expect("unable to load library reader");
+        let mut reader = FastqGz::new("data/test/test.fastq.gz")
+            .expect("unable to create fastqgz reader");
+        library.match_reader(&mut reader, 0);
+        library.write_count_table(&filenames[idx], vec![["full", "panel"][idx]])
+            .expect("unable to write count table");
+    }
+
+    let mut merged = MergedTable::read(&filenames[0], &[]).expect("unable to read count table");
+    merged.merge(MergedTable::read(&filenames[1], &[]).expect("unable to read count table"))
+        .expect("unable to merge count tables");
+    // samples must be unique across tables
+    assert!(merged.clone().merge(MergedTable::read(&filenames[1], &[]).unwrap()).is_err());
+    for filename in filenames.iter() {
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    assert_eq!(merged.metadata_names, vec!["Gene"]);
+    assert_eq!(merged.samples, vec!["full", "panel"]);
+    assert_eq!(merged.guides.len(), 1924);
+    let panel: Vec<usize> = (0..merged.guides.len())
+        .filter(|g| merged.guides[*g].starts_with("AADACL2_"))
+        .collect();
+    assert!(!panel.is_empty());
+    for (g, counts) in merged.counts.iter().enumerate() {
+        if panel.contains(&g) {
+            assert_eq!(counts[0], counts[1]);
+        } else {
+            assert_eq!(counts[1], "0");
+        }
+    }
+}
+
+#[test]
+fn test_paired_matching() {
+    let guide_seq = "GTTTAAGAG"; 
+    let mut library = Library::new(guide_seq, 1);
+    library.load_library("data/test/library.fasta")
+        .expect("unable to load library")
+        .expect("unable to load library reader");
+
+    // identical mates are counted once per pair
+    let mut r1 = FastqGz::new("data/test/test.fastq.gz")
+        .expect("unable to create fastqgz reader");
+    let mut r2 = FastqGz::new("data/test/test.fastq.gz")
+        .expect("unable to create fastqgz reader");
+    library.match_paired_reader(&mut r1, &mut r2, 0);
+    assert_eq!(library.sample_stats(0).num_assigned, 2017);
+
+    // pairs lacking the guide sequence in both mates are not counted
+    let total = |library: &Library| (0..library.num_guides())
+        .map(|g| library.get_counts(g)[0])
+        .sum::<u32>();
+    library.match_pair_bytes(b"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA", b"NNNN", 0);
+    assert_eq!(total(&library), 2017);
+
+    // the second mate is searched when the first lacks the guide sequence
+    // (reads carry at least one base ahead of the protospacer)
+    let contents = std::fs::read_to_string("data/test/library.fasta").unwrap();
+    let seq = format!("A{}", contents.lines().nth(1).unwrap());
+    let before = library.get_counts(0)[0];
+    library.match_pair_bytes(b"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA", seq.as_bytes(), 0);
+    assert_eq!(library.get_counts(0)[0], before + 1);
+    assert_eq!(total(&library), 2018);
+}
+
+#[test]
+fn test_long_guides() {
+    // a 20bp spacer with a 12bp anchor is too long to pack into a key
+    let anchor = "GTTTAAGAGCTA";
+    let spacer = "ACGTACGTACGTACGTACGT";
+    let filename = std::env::temp_dir().join(format!("screenr_long_{}.fasta", std::process::id()));
+    std::fs::write(&filename, format!(">LONG_1\n{}\n>LONG_2\n{}\n", spacer, "T".repeat(20))).unwrap();
+
+    let mut library = Library::new(anchor, 1);
+    library.set_spacer_len(20);
+    library.load_library(filename.to_str().unwrap())
+        .expect("unable to load library")
+        .expect("unable to load library reader");
+    std::fs::remove_file(&filename).unwrap();
+    assert_eq!(library.load_stats().num_unpackable, 0);
+
+    // forward and reverse complement reads both match
+    let read = format!("C{}{}CCCC", spacer, anchor);
+    library.match_seq_bytes(read.as_bytes(), 0);
+    library.match_seq_bytes(screenr::crispr::reverse_complement(&read).as_bytes(), 0);
+    assert_eq!(library.get_counts(0), &[2]);
+    assert_eq!(library.get_counts(1), &[0]);
+}
+
+#[test]
+fn test_repeated_anchor() {
+    let anchor = "GTTTAAGAG";
+    let spacer = "ACGTTGCAACGTTGCAACG";
+    let filename = std::env::temp_dir().join(format!("screenr_anchor_{}.fasta", std::process::id()));
+    std::fs::write(&filename, format!(">GENE_1\n{}\n", spacer)).unwrap();
+
+    let mut library = Library::new(anchor, 1);
+    library.load_library(filename.to_str().unwrap())
+        .expect("unable to load library")
+        .expect("unable to load library reader");
+    std::fs::remove_file(&filename).unwrap();
+
+    // a guide sequence too early to follow a protospacer is skipped for the next one,
+    // otherwise the first match is used, on either strand
+    for read in [format!("{}C{}{}CC", anchor, spacer, anchor), format!("C{}{}{}", spacer, anchor, anchor)] {
+        library.match_seq_bytes(read.as_bytes(), 0);
+        library.match_seq_bytes(screenr::crispr::reverse_complement(&read).as_bytes(), 0);
+    }
+    assert_eq!(library.get_counts(0), &[4]);
+}